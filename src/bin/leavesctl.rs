@@ -0,0 +1,150 @@
+use std::error::Error as StdError;
+use std::fs::File;
+use std::io;
+use std::path::PathBuf;
+use std::process;
+
+use structopt::StructOpt;
+
+use leaves::migrate::{self, Change, DumpFormat};
+use leaves::Leaf;
+
+/// Manage tags stored in a leaves backend.
+#[derive(StructOpt)]
+#[structopt(name = "leavesctl")]
+struct Opt {
+    /// Backend to manage, selected by scheme:
+    /// mysql://, postgres://, sqlite://, redis://, mongodb:// or http://
+    #[structopt(short, long, env = "LEAVES_BACKEND_URL")]
+    url: String,
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    /// List all tags
+    List,
+    /// Show a tag
+    Show { tag: i32 },
+    /// Create a tag
+    Create {
+        tag: i32,
+        #[structopt(long, default_value = "0")]
+        max_id: i64,
+        #[structopt(long, default_value = "1000")]
+        step: i32,
+    },
+    /// Change the step of a tag
+    SetStep { tag: i32, step: i32 },
+    /// Move `max_id` of a tag forward by `n`
+    Bump { tag: i32, n: i32 },
+    /// Delete a tag
+    Delete { tag: i32 },
+    /// Allocate a range of `n` IDs, or `step` IDs if `n` is omitted
+    Allocate { tag: i32, n: Option<i32> },
+    /// Dump all tags to a file, or stdout if omitted
+    Export {
+        file: Option<PathBuf>,
+        /// json or csv
+        #[structopt(long, default_value = "json")]
+        format: DumpFormat,
+    },
+    /// Load tags from a dump
+    Import {
+        file: PathBuf,
+        /// json or csv
+        #[structopt(long, default_value = "json")]
+        format: DumpFormat,
+        /// added to every `max_id` so IDs handed out after the dump are never reused
+        #[structopt(long, default_value = "0")]
+        margin: i64,
+        /// print the changes only
+        #[structopt(long)]
+        dry_run: bool,
+    },
+    /// Copy all tags to another backend
+    Migrate {
+        /// url of the target backend
+        #[structopt(long)]
+        to: String,
+        /// added to every `max_id` so IDs handed out during migration are never reused
+        #[structopt(long)]
+        margin: i64,
+        /// print the changes only
+        #[structopt(long)]
+        dry_run: bool,
+    },
+}
+
+#[tokio::main]
+async fn main() {
+    let opt = Opt::from_args();
+    if let Err(err) = run(opt).await {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
+
+async fn run(opt: Opt) -> std::result::Result<(), Box<dyn StdError>> {
+    let dao = leaves::dao::connect(&opt.url).await?;
+    match opt.command {
+        Command::List => {
+            let mut leaves = dao.leaves().await?;
+            leaves.sort_by_key(|leaf| leaf.tag);
+            println!("{:>10} {:>20} {:>10}", "TAG", "MAX_ID", "STEP");
+            for leaf in leaves {
+                print_leaf(&leaf);
+            }
+        }
+        Command::Show { tag } => print_leaf(&dao.leaf(tag).await?),
+        Command::Create { tag, max_id, step } => {
+            dao.insert(Leaf { tag, max_id, step }).await?;
+            print_leaf(&dao.leaf(tag).await?);
+        }
+        Command::SetStep { tag, step } => print_leaf(&dao.update_step(tag, step).await?),
+        Command::Delete { tag } => dao.delete(tag).await?,
+        Command::Bump { tag, n } => print_leaf(&dao.update_max_by_step(tag, n).await?),
+        Command::Allocate { tag, n } => {
+            let (leaf, n) = match n {
+                Some(n) => (dao.update_max_by_step(tag, n).await?, n),
+                None => {
+                    let leaf = dao.update_max(tag).await?;
+                    (leaf, leaf.step)
+                }
+            };
+            println!("{}..{}", leaf.max_id - n as i64, leaf.max_id);
+        }
+        Command::Export { file, format } => match file {
+            Some(file) => migrate::export(&dao, File::create(file)?, format).await?,
+            None => migrate::export(&dao, io::stdout(), format).await?,
+        },
+        Command::Import {
+            file,
+            format,
+            margin,
+            dry_run,
+        } => {
+            print_changes(&migrate::import(&dao, File::open(file)?, format, margin, dry_run).await?)
+        }
+        Command::Migrate {
+            to,
+            margin,
+            dry_run,
+        } => {
+            let target = leaves::dao::connect(&to).await?;
+            print_changes(&migrate::migrate(&dao, &target, margin, dry_run).await?);
+        }
+    }
+    Ok(())
+}
+
+fn print_leaf(leaf: &Leaf) {
+    println!("{:>10} {:>20} {:>10}", leaf.tag, leaf.max_id, leaf.step);
+}
+
+fn print_changes(changes: &[Change]) {
+    for change in changes {
+        println!("{}", change);
+    }
+}
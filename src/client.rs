@@ -0,0 +1,59 @@
+//! Client of a remote leaves server.
+//!
+//! [`RemoteLeafClient`] prefetches ranges of IDs per tag into a local double buffer,
+//! so a network round trip happens once per range instead of once per ID.
+//! See [`RemoteLeafDao`] for the protocol spoken with the server.
+use std::sync::Arc;
+
+use crate::codec::Codec;
+use crate::dao::RemoteLeafDao;
+use crate::segment::Config;
+use crate::{Result, SegmentIDGen};
+
+/// Generates IDs from ranges allocated by a remote leaves server.
+///
+/// # Examples
+/// ```no_run
+/// # async fn run() -> leaves::Result<()> {
+/// use leaves::client::RemoteLeafClient;
+/// use leaves::segment::Config;
+///
+/// let client = RemoteLeafClient::connect("http://127.0.0.1:8080", Config::lazy()).await?;
+/// for _ in 0..100 {
+///     client.get(1).await?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct RemoteLeafClient {
+    gen: SegmentIDGen<RemoteLeafDao>,
+}
+
+impl RemoteLeafClient {
+    /// `url` is like `http://host:port/prefix`, https is not supported.
+    pub async fn connect(url: &str, config: Config) -> Result<Self> {
+        let mut gen = SegmentIDGen::new(Arc::new(RemoteLeafDao::new(url)?), config);
+        gen.init().await?;
+        Ok(Self { gen })
+    }
+
+    /// Get an ID, the server is asked for a new range only when the local ones run out.
+    pub async fn get(&self, tag: i32) -> Result<i64> {
+        self.gen.get(tag).await
+    }
+
+    /// Render IDs of `tag` by `codec` in [`get_string`](RemoteLeafClient::get_string).
+    pub fn set_tag_codec(&mut self, tag: i32, codec: impl Codec + 'static) {
+        self.gen.set_tag_codec(tag, codec);
+    }
+
+    /// Get an ID rendered by the codec of `tag`, decimal by default.
+    pub async fn get_string(&self, tag: i32) -> Result<String> {
+        self.gen.get_string(tag).await
+    }
+
+    /// The ID [`get_string`](RemoteLeafClient::get_string) rendered into `s`.
+    pub fn decode_string(&self, tag: i32, s: &str) -> Result<i64> {
+        self.gen.decode_string(tag, s)
+    }
+}
@@ -0,0 +1,78 @@
+//! Time as seen by generators, so timing logic can be tested without waiting.
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+/// A source of [`Instant`]s, and of the wall-clock time.
+pub trait Clock: fmt::Debug + Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// time of day, for sequences starting over periodically
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// The monotonic clock of the OS, the default.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    #[inline]
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock standing still until it's advanced, for tests.
+///
+/// # Examples
+/// ```
+/// use std::time::Duration;
+/// use leaves::clock::{Clock, ManualClock};
+///
+/// let clock = ManualClock::new();
+/// let start = clock.now();
+/// clock.advance(Duration::from_secs(60));
+/// assert_eq!(clock.now() - start, Duration::from_secs(60));
+/// ```
+#[derive(Debug)]
+pub struct ManualClock {
+    now: Mutex<(Instant, SystemTime)>,
+}
+
+impl ManualClock {
+    /// Starts at the current time.
+    pub fn new() -> Self {
+        Self::at(SystemTime::now())
+    }
+
+    /// Starts at `system_time`.
+    pub fn at(system_time: SystemTime) -> Self {
+        Self {
+            now: Mutex::new((Instant::now(), system_time)),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let mut now = self.now.lock().unwrap();
+        now.0 += duration;
+        now.1 += duration;
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.now.lock().unwrap().0
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.now.lock().unwrap().1
+    }
+}
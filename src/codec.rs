@@ -0,0 +1,332 @@
+//! Short strings for IDs, like in URLs.
+//!
+//! Every [`Codec`] turns non-negative IDs into strings and back:
+//!
+//! * [`Decimal`]: `12345`, the default of [`SegmentIDGen::get_string`](crate::SegmentIDGen::get_string)
+//! * [`Base62`]: `3D7`, digits, upper and lower case letters
+//! * [`Crockford`]: `C1S`, Crockford's base32, case-insensitive and without `I`, `L`, `O`
+//!   and `U`, which are easy to confuse
+//! * [`Hashids`]: `NkK9`, compatible with the [Hashids](https://hashids.org) libraries
+//!   of other languages, given the same salt, minimum length and alphabet
+//!
+//! # Examples
+//! ```
+//! use leaves::codec::{Base62, Codec, Crockford, Hashids};
+//!
+//! assert_eq!(Base62.encode(12345).unwrap(), "3D7");
+//! assert_eq!(Crockford.decode("c1s").unwrap(), 12345);
+//! let hashids = Hashids::new("this is my salt", 0);
+//! assert_eq!(hashids.encode(12345).unwrap(), "NkK9");
+//! assert_eq!(hashids.decode("NkK9").unwrap(), 12345);
+//! ```
+use std::fmt;
+
+use crate::{Error, Result};
+
+/// Turns IDs into strings and back.
+pub trait Codec: fmt::Debug + Send + Sync {
+    /// fails on negative IDs
+    fn encode(&self, id: i64) -> Result<String>;
+    /// fails with [`Error::InvalidId`] on strings not encoded by this codec
+    fn decode(&self, s: &str) -> Result<i64>;
+}
+
+/// IDs in decimal.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Decimal;
+
+impl Codec for Decimal {
+    fn encode(&self, id: i64) -> Result<String> {
+        Ok(id.to_string())
+    }
+
+    fn decode(&self, s: &str) -> Result<i64> {
+        s.parse().map_err(|_| invalid(s))
+    }
+}
+
+const BASE62: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// IDs in base62, `0-9`, `A-Z` then `a-z`.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Base62;
+
+impl Codec for Base62 {
+    fn encode(&self, id: i64) -> Result<String> {
+        encode_radix(id, BASE62)
+    }
+
+    fn decode(&self, s: &str) -> Result<i64> {
+        decode_radix(s, 62, |c| match c {
+            b'0'..=b'9' => Some(c - b'0'),
+            b'A'..=b'Z' => Some(c - b'A' + 10),
+            b'a'..=b'z' => Some(c - b'a' + 36),
+            _ => None,
+        })
+    }
+}
+
+const CROCKFORD: &[u8] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// IDs in Crockford's base32, encoded in upper case.
+///
+/// Decoding ignores case and hyphens, and reads `I` and `L` as `1`, `O` as `0`.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Crockford;
+
+impl Codec for Crockford {
+    fn encode(&self, id: i64) -> Result<String> {
+        encode_radix(id, CROCKFORD)
+    }
+
+    fn decode(&self, s: &str) -> Result<i64> {
+        let normalized = s
+            .bytes()
+            .filter(|c| *c != b'-')
+            .map(|c| match c.to_ascii_uppercase() {
+                b'I' | b'L' => b'1',
+                b'O' => b'0',
+                c => c,
+            })
+            .collect::<Vec<_>>();
+        let normalized = String::from_utf8(normalized).map_err(|_| invalid(s))?;
+        decode_radix(&normalized, 32, |c| {
+            CROCKFORD
+                .iter()
+                .position(|digit| *digit == c)
+                .map(|digit| digit as u8)
+        })
+        .map_err(|_| invalid(s))
+    }
+}
+
+fn invalid(s: &str) -> Error {
+    Error::InvalidId(format!("`{}` can't be decoded", s))
+}
+
+fn negative(id: i64) -> Error {
+    Error::InvalidId(format!("{} is negative", id))
+}
+
+fn encode_radix(id: i64, alphabet: &[u8]) -> Result<String> {
+    if id < 0 {
+        return Err(negative(id));
+    }
+    let digits = to_alphabet(id as u64, alphabet);
+    Ok(String::from_utf8(digits).expect("alphabets are ASCII"))
+}
+
+fn decode_radix(s: &str, radix: u64, digit: impl Fn(u8) -> Option<u8>) -> Result<i64> {
+    if s.is_empty() {
+        return Err(invalid(s));
+    }
+    let mut id: i64 = 0;
+    for c in s.bytes() {
+        let digit = digit(c).ok_or_else(|| invalid(s))?;
+        id = id
+            .checked_mul(radix as i64)
+            .and_then(|id| id.checked_add(digit as i64))
+            .ok_or_else(|| invalid(s))?;
+    }
+    Ok(id)
+}
+
+fn to_alphabet(mut number: u64, alphabet: &[u8]) -> Vec<u8> {
+    let len = alphabet.len() as u64;
+    let mut digits = vec![];
+    loop {
+        digits.push(alphabet[(number % len) as usize]);
+        number /= len;
+        if number == 0 {
+            break;
+        }
+    }
+    digits.reverse();
+    digits
+}
+
+fn from_alphabet(digits: &[u8], alphabet: &[u8]) -> Option<u64> {
+    let len = alphabet.len() as u64;
+    digits.iter().try_fold(0u64, |number, c| {
+        let digit = alphabet.iter().position(|a| a == c)? as u64;
+        number.checked_mul(len)?.checked_add(digit)
+    })
+}
+
+const HASHIDS_ALPHABET: &str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ1234567890";
+const HASHIDS_SEPS: &[u8] = b"cfhistuCFHISTU";
+const SEP_DIV: f64 = 3.5;
+const GUARD_DIV: f64 = 12.0;
+
+/// Hashids of single IDs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hashids {
+    salt: Vec<u8>,
+    min_length: usize,
+    alphabet: Vec<u8>,
+    seps: Vec<u8>,
+    guards: Vec<u8>,
+}
+
+impl Hashids {
+    /// Hashids of the default alphabet, padded to `min_length` characters.
+    pub fn new(salt: &str, min_length: usize) -> Self {
+        Self::with_alphabet(salt, min_length, HASHIDS_ALPHABET).expect("valid default alphabet")
+    }
+
+    /// `alphabet` needs 16 unique ASCII characters at least, and no spaces.
+    pub fn with_alphabet(salt: &str, min_length: usize, alphabet: &str) -> Result<Self> {
+        let mut unique = vec![];
+        for c in alphabet.bytes() {
+            if !unique.contains(&c) {
+                unique.push(c);
+            }
+        }
+        if !alphabet.is_ascii() || unique.contains(&b' ') || unique.len() < 16 {
+            return Err(Error::InvalidConfig(format!(
+                "hashids alphabet `{}` needs 16 unique ASCII characters and no spaces",
+                alphabet
+            )));
+        }
+        let salt = salt.as_bytes().to_vec();
+
+        let mut seps = HASHIDS_SEPS
+            .iter()
+            .copied()
+            .filter(|c| unique.contains(c))
+            .collect::<Vec<_>>();
+        let mut alphabet = unique
+            .into_iter()
+            .filter(|c| !seps.contains(c))
+            .collect::<Vec<_>>();
+        shuffle(&mut seps, &salt);
+        if seps.is_empty() || alphabet.len() as f64 / seps.len() as f64 > SEP_DIV {
+            let seps_len = ((alphabet.len() as f64 / SEP_DIV).ceil() as usize).max(2);
+            if seps_len > seps.len() {
+                let diff = seps_len - seps.len();
+                seps.extend(alphabet.drain(..diff));
+            } else {
+                seps.truncate(seps_len);
+            }
+        }
+        shuffle(&mut alphabet, &salt);
+
+        let guard_count = (alphabet.len() as f64 / GUARD_DIV).ceil() as usize;
+        let guards = if alphabet.len() < 3 {
+            seps.drain(..guard_count).collect()
+        } else {
+            alphabet.drain(..guard_count).collect()
+        };
+        Ok(Self {
+            salt,
+            min_length,
+            alphabet,
+            seps,
+            guards,
+        })
+    }
+
+    fn encode_numbers(&self, numbers: &[u64]) -> Vec<u8> {
+        let mut alphabet = self.alphabet.clone();
+        let numbers_hash = numbers
+            .iter()
+            .enumerate()
+            .map(|(i, number)| number % (i as u64 + 100))
+            .sum::<u64>();
+        let lottery = alphabet[(numbers_hash % alphabet.len() as u64) as usize];
+        let mut hash = vec![lottery];
+        for (i, &number) in numbers.iter().enumerate() {
+            self.shuffle_by_lottery(&mut alphabet, lottery);
+            let last = to_alphabet(number, &alphabet);
+            hash.extend_from_slice(&last);
+            if i + 1 < numbers.len() {
+                let number = number % (last[0] as u64 + i as u64);
+                hash.push(self.seps[(number % self.seps.len() as u64) as usize]);
+            }
+        }
+
+        if hash.len() < self.min_length {
+            let guard = (numbers_hash + hash[0] as u64) % self.guards.len() as u64;
+            hash.insert(0, self.guards[guard as usize]);
+            if hash.len() < self.min_length {
+                let guard = (numbers_hash + hash[2] as u64) % self.guards.len() as u64;
+                hash.push(self.guards[guard as usize]);
+            }
+        }
+        let half = alphabet.len() / 2;
+        while hash.len() < self.min_length {
+            let salt = alphabet.clone();
+            shuffle(&mut alphabet, &salt);
+            let mut padded = alphabet[half..].to_vec();
+            padded.extend_from_slice(&hash);
+            padded.extend_from_slice(&alphabet[..half]);
+            hash = padded;
+            let excess = hash.len().saturating_sub(self.min_length);
+            if excess > 0 {
+                let start = excess / 2;
+                hash = hash[start..start + self.min_length].to_vec();
+            }
+        }
+        hash
+    }
+
+    fn decode_numbers(&self, hash: &[u8]) -> Option<Vec<u64>> {
+        let parts = hash.split(|c| self.guards.contains(c)).collect::<Vec<_>>();
+        let part = match parts.len() {
+            2 | 3 => parts[1],
+            _ => parts[0],
+        };
+        let (&lottery, part) = part.split_first()?;
+        let mut alphabet = self.alphabet.clone();
+        let mut numbers = vec![];
+        for digits in part.split(|c| self.seps.contains(c)) {
+            self.shuffle_by_lottery(&mut alphabet, lottery);
+            numbers.push(from_alphabet(digits, &alphabet)?);
+        }
+        // it's decoded from a hash not encoded by us otherwise
+        if self.encode_numbers(&numbers) != hash {
+            return None;
+        }
+        Some(numbers)
+    }
+
+    fn shuffle_by_lottery(&self, alphabet: &mut [u8], lottery: u8) {
+        let mut salt = vec![lottery];
+        salt.extend_from_slice(&self.salt);
+        salt.extend_from_slice(alphabet);
+        salt.truncate(alphabet.len());
+        shuffle(alphabet, &salt);
+    }
+}
+
+impl Codec for Hashids {
+    fn encode(&self, id: i64) -> Result<String> {
+        if id < 0 {
+            return Err(negative(id));
+        }
+        let hash = self.encode_numbers(&[id as u64]);
+        Ok(String::from_utf8(hash).expect("alphabets are ASCII"))
+    }
+
+    fn decode(&self, s: &str) -> Result<i64> {
+        match self.decode_numbers(s.as_bytes()).as_deref() {
+            Some(&[id]) if id <= i64::MAX as u64 => Ok(id as i64),
+            _ => Err(invalid(s)),
+        }
+    }
+}
+
+/// The consistent shuffle of Hashids.
+fn shuffle(alphabet: &mut [u8], salt: &[u8]) {
+    if salt.is_empty() {
+        return;
+    }
+    let mut p = 0;
+    for (v, i) in (1..alphabet.len()).rev().enumerate() {
+        let v = v % salt.len();
+        let integer = salt[v] as usize;
+        p += integer;
+        let j = (integer + v + p) % i;
+        alphabet.swap(i, j);
+    }
+}
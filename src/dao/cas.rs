@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use crate::{Error, Leaf, Result};
+
+use super::{decode_leaf, encode_leaf, grow, LeafDao};
+
+/// A key-value store offering compare-and-set only, like etcd, Consul or FoundationDB.
+///
+/// Implementing it is all a new backend needs, [`CasLeafDao`] does the rest.
+#[async_trait]
+pub trait CasStore {
+    /// get the value of `key`
+    async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>>;
+    /// set `key` to `new` if its value is still `old`, `None` means absent.
+    /// returns `false` on conflict.
+    async fn compare_and_set(
+        &self,
+        key: &[u8],
+        old: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<bool>;
+}
+
+/// `LeafDao` on top of a [`CasStore`], retrying on conflict.
+///
+/// A leaf is stored under `{prefix}{tag}`, and the list of tags under `{prefix}tags`.
+///
+/// # Examples
+/// ```no_run
+/// # async fn run() -> leaves::Result<()> {
+/// use leaves::dao::{CasLeafDao, MemoryCasStore};
+/// use leaves::LeafDao;
+///
+/// let dao = CasLeafDao::new(MemoryCasStore::default());
+/// let leaf = dao.update_max(1).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct CasLeafDao<S> {
+    store: S,
+    prefix: String,
+}
+
+impl<S: CasStore + Send + Sync> CasLeafDao<S> {
+    /// Keys are prefixed with `leaves/`.
+    pub fn new(store: S) -> Self {
+        Self::with_prefix(store, "leaves/")
+    }
+
+    pub fn with_prefix(store: S, prefix: impl Into<String>) -> Self {
+        Self {
+            store,
+            prefix: prefix.into(),
+        }
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    fn leaf_key(&self, tag: i32) -> Vec<u8> {
+        format!("{}{}", self.prefix, tag).into_bytes()
+    }
+
+    fn tags_key(&self) -> Vec<u8> {
+        format!("{}tags", self.prefix).into_bytes()
+    }
+
+    /// Apply `f` to the value of `key` until no one else changed it in between.
+    async fn modify<T>(
+        &self,
+        key: &[u8],
+        f: impl Fn(Option<&[u8]>) -> Result<(Option<Vec<u8>>, T)>,
+    ) -> Result<T> {
+        loop {
+            let old = self.store.get(key).await?;
+            let (new, result) = f(old.as_deref())?;
+            if new == old
+                || self
+                    .store
+                    .compare_and_set(key, old.as_deref(), new.as_deref())
+                    .await?
+            {
+                return Ok(result);
+            }
+            tracing::debug!("conflict on {}, retrying", String::from_utf8_lossy(key));
+        }
+    }
+
+    async fn update(&self, tag: i32, f: impl Fn(&mut Leaf) -> Result<()>) -> Result<Leaf> {
+        self.modify(&self.leaf_key(tag), |value| {
+            let mut leaf = decode_leaf(tag, value.ok_or(Error::TagNotExist)?)?;
+            f(&mut leaf)?;
+            Ok((Some(encode_leaf(&leaf)), leaf))
+        })
+        .await
+    }
+
+    async fn put(&self, leaf: Leaf, overwrite: bool) -> Result<()> {
+        // listed before it exists, so a leaf is never missing from `tags`
+        self.update_tags(|tags| {
+            if !tags.contains(&leaf.tag) {
+                tags.push(leaf.tag);
+            }
+        })
+        .await?;
+        self.modify(&self.leaf_key(leaf.tag), |value| {
+            if value.is_some() && !overwrite {
+                return Err(Error::TagExists);
+            }
+            Ok((Some(encode_leaf(&leaf)), ()))
+        })
+        .await
+    }
+
+    async fn update_tags(&self, f: impl Fn(&mut Vec<i32>)) -> Result<()> {
+        self.modify(&self.tags_key(), |value| {
+            let mut tags = decode_tags(value.unwrap_or_default())?;
+            f(&mut tags);
+            Ok((Some(encode_tags(&tags)), ()))
+        })
+        .await
+    }
+}
+
+fn encode_tags(tags: &[i32]) -> Vec<u8> {
+    tags.iter().flat_map(|tag| tag.to_be_bytes()).collect()
+}
+
+fn decode_tags(value: &[u8]) -> Result<Vec<i32>> {
+    let tags = value.chunks_exact(4);
+    if !tags.remainder().is_empty() {
+        return Err(Error::SerializationError);
+    }
+    Ok(tags
+        .map(|tag| i32::from_be_bytes(tag.try_into().unwrap()))
+        .collect())
+}
+
+#[async_trait]
+impl<S: CasStore + Send + Sync> LeafDao for CasLeafDao<S> {
+    async fn leaves(&self) -> Result<Vec<Leaf>> {
+        let mut leaves = vec![];
+        for tag in self.tags().await? {
+            // deleted after the tags were read
+            if let Some(value) = self.store.get(&self.leaf_key(tag)).await? {
+                leaves.push(decode_leaf(tag, &value)?);
+            }
+        }
+        Ok(leaves)
+    }
+
+    async fn leaf(&self, tag: i32) -> Result<Leaf> {
+        let value = self.store.get(&self.leaf_key(tag)).await?;
+        decode_leaf(tag, &value.ok_or(Error::TagNotExist)?)
+    }
+
+    async fn insert(&self, leaf: Leaf) -> Result<()> {
+        self.put(leaf, false).await
+    }
+
+    async fn tags(&self) -> Result<Vec<i32>> {
+        let value = self.store.get(&self.tags_key()).await?;
+        decode_tags(&value.unwrap_or_default())
+    }
+
+    async fn update_max(&self, tag: i32) -> Result<Leaf> {
+        self.update(tag, |leaf| {
+            let step = leaf.step;
+            grow(leaf, step, None)
+        })
+        .await
+    }
+
+    async fn update_max_by_step(&self, tag: i32, step: i32) -> Result<Leaf> {
+        self.update(tag, |leaf| grow(leaf, step, None)).await
+    }
+
+    async fn update_max_by_step_up_to(&self, tag: i32, step: i32, max_value: i64) -> Result<Leaf> {
+        self.update(tag, |leaf| grow(leaf, step, Some(max_value)))
+            .await
+    }
+
+    async fn update_step(&self, tag: i32, step: i32) -> Result<Leaf> {
+        self.update(tag, |leaf| {
+            leaf.step = step;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete(&self, tag: i32) -> Result<()> {
+        self.modify(&self.leaf_key(tag), |value| match value {
+            Some(_) => Ok((None, ())),
+            None => Err(Error::TagNotExist),
+        })
+        .await?;
+        self.update_tags(|tags| tags.retain(|t| *t != tag)).await
+    }
+
+    async fn set_max_id_if_greater(&self, tag: i32, max_id: i64) -> Result<Leaf> {
+        self.update(tag, |leaf| {
+            leaf.max_id = leaf.max_id.max(max_id);
+            Ok(())
+        })
+        .await
+    }
+
+    async fn upsert(&self, leaf: Leaf) -> Result<()> {
+        self.put(leaf, true).await
+    }
+}
+
+/// An in-memory [`CasStore`], for tests.
+#[derive(Debug, Default)]
+pub struct MemoryCasStore {
+    values: Mutex<HashMap<Vec<u8>, Vec<u8>>>,
+}
+
+#[async_trait]
+impl CasStore for MemoryCasStore {
+    async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(self.values.lock().unwrap().get(key).cloned())
+    }
+
+    async fn compare_and_set(
+        &self,
+        key: &[u8],
+        old: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<bool> {
+        let mut values = self.values.lock().unwrap();
+        if values.get(key).map(Vec::as_slice) != old {
+            return Ok(false);
+        }
+        match new {
+            Some(new) => values.insert(key.to_vec(), new.to_vec()),
+            None => values.remove(key),
+        };
+        Ok(true)
+    }
+}
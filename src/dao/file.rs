@@ -0,0 +1,212 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use fs2::FileExt;
+
+use crate::{utils, Error, Leaf, Result};
+
+use super::{grow, LeafDao};
+
+type Leaves = BTreeMap<i32, Leaf>;
+
+/// Leaves stored in a local JSON file, for deployments without a database.
+///
+/// Every operation takes an OS lock on `<path>.lock` and reads the file again, so DAOs
+/// in different processes on the same host never allocate the same range.
+/// Updates are written to `<path>.tmp`, synced, and renamed over the file,
+/// a crash leaves either the old or the new content.
+///
+/// # Examples
+/// ```no_run
+/// # async fn run() -> leaves::Result<()> {
+/// use leaves::dao::FileLeafDao;
+/// use leaves::LeafDao;
+///
+/// let dao = FileLeafDao::new("/var/lib/leaves/leaves.json");
+/// let leaf = dao.update_max(1).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct FileLeafDao {
+    path: PathBuf,
+}
+
+impl FileLeafDao {
+    /// The file is created on the first write, its directory must exist.
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    async fn read<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Leaves) -> Result<T> + Send + 'static,
+    {
+        let path = self.path.clone();
+        utils::spawn_blocking(move || {
+            let lock = lock_file(&path)?;
+            lock.lock_shared()?;
+            f(&load(&path)?)
+        })
+        .await
+    }
+
+    async fn write<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Leaves) -> Result<T> + Send + 'static,
+    {
+        let path = self.path.clone();
+        utils::spawn_blocking(move || {
+            let lock = lock_file(&path)?;
+            lock.lock_exclusive()?;
+            let mut leaves = load(&path)?;
+            let result = f(&mut leaves)?;
+            store(&path, &leaves)?;
+            Ok(result)
+        })
+        .await
+    }
+
+    async fn update<F>(&self, tag: i32, f: F) -> Result<Leaf>
+    where
+        F: FnOnce(&mut Leaf) -> Result<()> + Send + 'static,
+    {
+        self.write(move |leaves| {
+            let leaf = leaves.get_mut(&tag).ok_or(Error::TagNotExist)?;
+            f(leaf)?;
+            Ok(*leaf)
+        })
+        .await
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    path.into()
+}
+
+/// The data file is replaced on every write, so the lock lives in a file of its own.
+fn lock_file(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(with_suffix(path, ".lock"))
+}
+
+fn load(path: &Path) -> Result<Leaves> {
+    let content = match fs::read(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Leaves::new()),
+        Err(err) => return Err(err.into()),
+    };
+    let leaves: Vec<Leaf> = serde_json::from_slice(&content)?;
+    Ok(leaves.into_iter().map(|leaf| (leaf.tag, leaf)).collect())
+}
+
+fn store(path: &Path, leaves: &Leaves) -> Result<()> {
+    let tmp = with_suffix(path, ".tmp");
+    let mut file = File::create(&tmp)?;
+    serde_json::to_writer_pretty(&mut file, &leaves.values().collect::<Vec<_>>())?;
+    file.write_all(b"\n")?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+    // make the rename itself durable
+    #[cfg(unix)]
+    {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl LeafDao for FileLeafDao {
+    async fn leaves(&self) -> Result<Vec<Leaf>> {
+        self.read(|leaves| Ok(leaves.values().copied().collect()))
+            .await
+    }
+
+    async fn leaf(&self, tag: i32) -> Result<Leaf> {
+        self.read(move |leaves| leaves.get(&tag).copied().ok_or(Error::TagNotExist))
+            .await
+    }
+
+    async fn insert(&self, leaf: Leaf) -> Result<()> {
+        self.write(move |leaves| {
+            if leaves.contains_key(&leaf.tag) {
+                return Err(Error::TagExists);
+            }
+            leaves.insert(leaf.tag, leaf);
+            Ok(())
+        })
+        .await
+    }
+
+    async fn tags(&self) -> Result<Vec<i32>> {
+        self.read(|leaves| Ok(leaves.keys().copied().collect()))
+            .await
+    }
+
+    async fn update_max(&self, tag: i32) -> Result<Leaf> {
+        self.update(tag, move |leaf| {
+            let step = leaf.step;
+            grow(leaf, step, None)
+        })
+        .await
+    }
+
+    async fn update_max_by_step(&self, tag: i32, step: i32) -> Result<Leaf> {
+        self.update(tag, move |leaf| grow(leaf, step, None)).await
+    }
+
+    async fn update_max_by_step_up_to(&self, tag: i32, step: i32, max_value: i64) -> Result<Leaf> {
+        self.update(tag, move |leaf| grow(leaf, step, Some(max_value)))
+            .await
+    }
+
+    async fn update_step(&self, tag: i32, step: i32) -> Result<Leaf> {
+        self.update(tag, move |leaf| {
+            leaf.step = step;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete(&self, tag: i32) -> Result<()> {
+        self.write(move |leaves| leaves.remove(&tag).map(|_| ()).ok_or(Error::TagNotExist))
+            .await
+    }
+
+    async fn set_max_id_if_greater(&self, tag: i32, max_id: i64) -> Result<Leaf> {
+        self.update(tag, move |leaf| {
+            leaf.max_id = leaf.max_id.max(max_id);
+            Ok(())
+        })
+        .await
+    }
+
+    async fn upsert(&self, leaf: Leaf) -> Result<()> {
+        self.write(move |leaves| {
+            leaves.insert(leaf.tag, leaf);
+            Ok(())
+        })
+        .await
+    }
+}
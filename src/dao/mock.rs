@@ -1,58 +1,448 @@
+//! An in-memory `LeafDao` injecting latency and faults, to test how generators behave
+//! when the database is slow or failing.
+//!
+//! # Examples
+//! ```no_run
+//! # async fn run() -> leaves::Result<()> {
+//! use std::time::Duration;
+//! use leaves::dao::mock::{Latency, MockLeafDao, Operation};
+//! use leaves::{Error, Leaf, LeafDao};
+//!
+//! let dao = MockLeafDao::default();
+//! dao.insert(Leaf { tag: 1, max_id: 0, step: 10 }).await?;
+//! dao.set_latency(Operation::UpdateMax, Latency::Fixed(Duration::from_millis(10)));
+//! // the first call succeeds, the second one fails
+//! dao.script(Operation::UpdateMax, vec![Ok(()), Err(Error::FaultInjected)]);
+//! assert!(dao.update_max(1).await.is_ok());
+//! assert!(dao.update_max(1).await.is_err());
+//! assert_eq!(dao.calls(Operation::UpdateMax), 2);
+//! # Ok(())
+//! # }
+//! ```
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
 use async_trait::async_trait;
+use dashmap::mapref::entry::Entry;
 use dashmap::DashMap;
-use std::time::Duration;
+use event_listener::Event;
+
+use crate::{utils::sleep, Error, FreeRange, Leaf, Result};
 
-use crate::{utils::sleep, Error, Leaf, Result};
+use super::{grow, LeafDao};
 
-use super::LeafDao;
+/// Methods of [`LeafDao`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Operation {
+    Leaves,
+    Leaf,
+    Insert,
+    Tags,
+    UpdateMax,
+    UpdateMaxByStep,
+    UpdateStep,
+    Delete,
+    SetMaxIdIfGreater,
+    Upsert,
+    PushFreeRange,
+    PopFreeRange,
+}
+
+/// How long an operation takes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Latency {
+    Fixed(Duration),
+    /// uniformly distributed in `[min, max]`
+    Uniform(Duration, Duration),
+}
 
+/// A call made to [`MockLeafDao`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Call {
+    pub operation: Operation,
+    /// `None` for operations on all leaves
+    pub tag: Option<i32>,
+    /// the leaf returned by updates
+    pub leaf: Option<Leaf>,
+    /// the call returned an error, injected or not
+    pub failed: bool,
+}
 
 #[derive(Debug, Default)]
+struct Faults {
+    latency: HashMap<Operation, Latency>,
+    error_rate: HashMap<Operation, f64>,
+    scripts: HashMap<Operation, VecDeque<Result<()>>>,
+    calls: HashMap<Operation, usize>,
+    history: Vec<Call>,
+    /// state of a xorshift generator, deterministic so failing runs can be replayed
+    seed: u64,
+}
+
+impl Faults {
+    fn random(&mut self) -> f64 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        (self.seed >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Leaves kept in memory.
+///
+/// Every operation waits while the DAO is [paused](MockLeafDao::pause), sleeps for its
+/// latency, then fails with the next outcome [scripted](MockLeafDao::script) or at its
+/// error rate. By default `update_max` and `update_max_by_step` take 200ms, like a
+/// database would, and nothing fails.
+#[derive(Debug)]
 pub struct MockLeafDao {
     leaves: DashMap<i32, Leaf>,
+    free_ranges: Mutex<Vec<FreeRange>>,
+    faults: Mutex<Faults>,
+    paused: AtomicBool,
+    resumed: Event,
+}
+
+impl Default for MockLeafDao {
+    fn default() -> Self {
+        let latency = Latency::Fixed(Duration::from_millis(200));
+        let mut faults = Faults {
+            seed: 0x2545_f491_4f6c_dd1d,
+            ..Default::default()
+        };
+        faults.latency.insert(Operation::UpdateMax, latency);
+        faults.latency.insert(Operation::UpdateMaxByStep, latency);
+        Self {
+            leaves: DashMap::new(),
+            free_ranges: Mutex::new(vec![]),
+            faults: Mutex::new(faults),
+            paused: AtomicBool::new(false),
+            resumed: Event::new(),
+        }
+    }
+}
+
+impl MockLeafDao {
+    /// Let `operation` take `latency`.
+    pub fn set_latency(&self, operation: Operation, latency: Latency) {
+        self.faults
+            .lock()
+            .unwrap()
+            .latency
+            .insert(operation, latency);
+    }
+
+    /// Let every operation return immediately.
+    pub fn clear_latency(&self) {
+        self.faults.lock().unwrap().latency.clear();
+    }
+
+    /// Fail `operation` with [`Error::FaultInjected`] at `rate`, from 0 to 1.
+    pub fn set_error_rate(&self, operation: Operation, rate: f64) {
+        self.faults
+            .lock()
+            .unwrap()
+            .error_rate
+            .insert(operation, rate);
+    }
+
+    /// Seed of the generator deciding which calls fail at the error rate.
+    pub fn set_seed(&self, seed: u64) {
+        // xorshift is stuck at 0
+        self.faults.lock().unwrap().seed = seed.max(1);
+    }
+
+    /// Outcomes of the next calls of `operation`, after the ones scripted before.
+    /// `Ok(())` runs the call, whatever the error rate, an error is returned as is.
+    pub fn script(&self, operation: Operation, outcomes: impl IntoIterator<Item = Result<()>>) {
+        self.faults
+            .lock()
+            .unwrap()
+            .scripts
+            .entry(operation)
+            .or_default()
+            .extend(outcomes);
+    }
+
+    /// Hold every operation until [`resume`](MockLeafDao::resume) is called.
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::Release);
+    }
+
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::Release);
+        self.resumed.notify(usize::MAX);
+    }
+
+    /// Calls of `operation` made so far, failed ones included.
+    pub fn calls(&self, operation: Operation) -> usize {
+        let faults = self.faults.lock().unwrap();
+        faults.calls.get(&operation).copied().unwrap_or(0)
+    }
+
+    /// Calls finished so far, in the order they finished.
+    pub fn history(&self) -> Vec<Call> {
+        self.faults.lock().unwrap().history.clone()
+    }
+
+    /// Forget the calls made so far.
+    pub fn clear_history(&self) {
+        let mut faults = self.faults.lock().unwrap();
+        faults.calls.clear();
+        faults.history.clear();
+    }
+
+    async fn wait_resumed(&self) {
+        while self.paused.load(Ordering::Acquire) {
+            let listener = self.resumed.listen();
+            if !self.paused.load(Ordering::Acquire) {
+                break;
+            }
+            listener.await;
+        }
+    }
+
+    /// Pause, sleep and maybe fail as configured for `operation`, then run `f`.
+    async fn call<T>(
+        &self,
+        operation: Operation,
+        tag: Option<i32>,
+        f: impl FnOnce() -> Result<T>,
+        leaf_of: impl FnOnce(&T) -> Option<Leaf>,
+    ) -> Result<T> {
+        self.wait_resumed().await;
+        let (latency, outcome) = {
+            let mut faults = self.faults.lock().unwrap();
+            *faults.calls.entry(operation).or_default() += 1;
+            let latency = match faults.latency.get(&operation).copied() {
+                Some(Latency::Fixed(latency)) => latency,
+                Some(Latency::Uniform(min, max)) => {
+                    min + max.saturating_sub(min).mul_f64(faults.random())
+                }
+                None => Duration::from_secs(0),
+            };
+            let scripted = faults
+                .scripts
+                .get_mut(&operation)
+                .and_then(VecDeque::pop_front);
+            let outcome = match scripted {
+                Some(outcome) => outcome,
+                None => {
+                    let rate = faults.error_rate.get(&operation).copied().unwrap_or(0.0);
+                    if rate > 0.0 && faults.random() < rate {
+                        Err(Error::FaultInjected)
+                    } else {
+                        Ok(())
+                    }
+                }
+            };
+            (latency, outcome)
+        };
+        if latency > Duration::from_secs(0) {
+            sleep(latency).await;
+        }
+        let result = outcome.and_then(|_| f());
+        self.faults.lock().unwrap().history.push(Call {
+            operation,
+            tag,
+            leaf: result.as_ref().ok().and_then(leaf_of),
+            failed: result.is_err(),
+        });
+        result
+    }
+
+    fn update(&self, tag: i32, f: impl FnOnce(&mut Leaf) -> Result<()>) -> Result<Leaf> {
+        let mut leaf = self.leaves.get_mut(&tag).ok_or(Error::TagNotExist)?;
+        f(&mut leaf)?;
+        Ok(*leaf)
+    }
+}
+
+fn no_leaf<T>(_: &T) -> Option<Leaf> {
+    None
+}
+
+fn some_leaf(leaf: &Leaf) -> Option<Leaf> {
+    Some(*leaf)
 }
 
 #[async_trait]
 impl LeafDao for MockLeafDao {
     async fn leaves(&self) -> Result<Vec<Leaf>> {
-        Ok(self.leaves.iter().map(|r| *r.value()).collect())
+        self.call(
+            Operation::Leaves,
+            None,
+            || Ok(self.leaves.iter().map(|r| *r.value()).collect()),
+            no_leaf,
+        )
+        .await
     }
 
     async fn leaf(&self, tag: i32) -> Result<Leaf> {
-        self.leaves
-            .get(&tag)
-            .map(|r| *r.value())
-            .ok_or(Error::TagNotExist)
+        self.call(
+            Operation::Leaf,
+            Some(tag),
+            || {
+                self.leaves
+                    .get(&tag)
+                    .map(|r| *r.value())
+                    .ok_or(Error::TagNotExist)
+            },
+            some_leaf,
+        )
+        .await
     }
 
     async fn insert(&self, leaf: Leaf) -> Result<()> {
-        self.leaves.insert(leaf.tag, leaf);
-        Ok(())
+        self.call(
+            Operation::Insert,
+            Some(leaf.tag),
+            || match self.leaves.entry(leaf.tag) {
+                Entry::Occupied(_) => Err(Error::TagExists),
+                Entry::Vacant(entry) => {
+                    entry.insert(leaf);
+                    Ok(())
+                }
+            },
+            no_leaf,
+        )
+        .await
     }
 
     async fn tags(&self) -> Result<Vec<i32>> {
-        Ok(self.leaves.iter().map(|r| *r.key()).collect())
+        self.call(
+            Operation::Tags,
+            None,
+            || Ok(self.leaves.iter().map(|r| *r.key()).collect()),
+            no_leaf,
+        )
+        .await
     }
 
     async fn update_max(&self, tag: i32) -> Result<Leaf> {
-        sleep(Duration::from_millis(200)).await;
-        self.leaves
-            .update_get(&tag, |_, leaf| {
-                let max_id = leaf.max_id + leaf.step as i64;
-                Leaf { max_id, ..*leaf }
-            })
-            .ok_or(Error::TagNotExist)
-            .map(|elem| *elem.value())
+        self.call(
+            Operation::UpdateMax,
+            Some(tag),
+            || {
+                self.update(tag, |leaf| {
+                    let step = leaf.step;
+                    grow(leaf, step, None)
+                })
+            },
+            some_leaf,
+        )
+        .await
     }
 
     async fn update_max_by_step(&self, tag: i32, step: i32) -> Result<Leaf> {
-        sleep(Duration::from_millis(200)).await;
-        self.leaves
-            .update_get(&tag, |_, leaf| {
-                let max_id = leaf.max_id + step as i64;
-                Leaf { max_id, ..*leaf }
-            })
-            .ok_or(Error::TagNotExist)
-            .map(|elem| *elem.value())
+        self.call(
+            Operation::UpdateMaxByStep,
+            Some(tag),
+            || self.update(tag, |leaf| grow(leaf, step, None)),
+            some_leaf,
+        )
+        .await
+    }
+
+    async fn update_max_by_step_up_to(&self, tag: i32, step: i32, max_value: i64) -> Result<Leaf> {
+        self.call(
+            Operation::UpdateMaxByStep,
+            Some(tag),
+            || self.update(tag, |leaf| grow(leaf, step, Some(max_value))),
+            some_leaf,
+        )
+        .await
+    }
+
+    async fn update_step(&self, tag: i32, step: i32) -> Result<Leaf> {
+        self.call(
+            Operation::UpdateStep,
+            Some(tag),
+            || {
+                self.update(tag, |leaf| {
+                    leaf.step = step;
+                    Ok(())
+                })
+            },
+            some_leaf,
+        )
+        .await
+    }
+
+    async fn delete(&self, tag: i32) -> Result<()> {
+        self.call(
+            Operation::Delete,
+            Some(tag),
+            || {
+                self.leaves
+                    .remove(&tag)
+                    .map(|_| ())
+                    .ok_or(Error::TagNotExist)
+            },
+            no_leaf,
+        )
+        .await
+    }
+
+    async fn set_max_id_if_greater(&self, tag: i32, max_id: i64) -> Result<Leaf> {
+        self.call(
+            Operation::SetMaxIdIfGreater,
+            Some(tag),
+            || {
+                self.update(tag, |leaf| {
+                    leaf.max_id = leaf.max_id.max(max_id);
+                    Ok(())
+                })
+            },
+            some_leaf,
+        )
+        .await
+    }
+
+    async fn upsert(&self, leaf: Leaf) -> Result<()> {
+        self.call(
+            Operation::Upsert,
+            Some(leaf.tag),
+            || {
+                self.leaves.insert(leaf.tag, leaf);
+                Ok(())
+            },
+            no_leaf,
+        )
+        .await
+    }
+
+    async fn push_free_range(&self, range: FreeRange) -> Result<()> {
+        self.call(
+            Operation::PushFreeRange,
+            Some(range.tag),
+            || {
+                self.free_ranges.lock().unwrap().push(range);
+                Ok(())
+            },
+            no_leaf,
+        )
+        .await
+    }
+
+    async fn pop_free_range(&self, tag: i32) -> Result<Option<FreeRange>> {
+        self.call(
+            Operation::PopFreeRange,
+            Some(tag),
+            || {
+                let mut ranges = self.free_ranges.lock().unwrap();
+                let lowest = ranges
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, range)| range.tag == tag)
+                    .min_by_key(|(_, range)| range.start)
+                    .map(|(i, _)| i);
+                Ok(lowest.map(|i| ranges.remove(i)))
+            },
+            no_leaf,
+        )
+        .await
     }
 }
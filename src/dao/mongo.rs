@@ -1,66 +1,181 @@
-use futures_util::StreamExt;
+use futures_util::TryStreamExt;
+use mongodb::error::{ErrorKind, WriteFailure};
+use mongodb::options::{
+    Acknowledgment, ClientOptions, DeleteOptions, FindOneAndUpdateOptions, FindOneOptions,
+    FindOptions, ReadConcern, ReadPreference, ReplaceOptions, ReturnDocument, SelectionCriteria,
+    UpdateOptions, WriteConcern,
+};
 use mongodb::Collection;
 
 use async_trait::async_trait;
 
 use crate::{Error, Leaf, LeafDao, Result};
 
+/// Code of a write violating a unique index.
+const DUPLICATE_KEY: i32 = 11000;
+
+/// Leaves stored in a MongoDB collection, one document per leaf.
+///
+/// [`insert`](LeafDao::insert) fails on a tag with a document already, but two inserts
+/// of a new tag racing may both succeed without a unique index on `tag`, which is
+/// worth creating, e.g. `db.leaf_alloc.createIndex({tag: 1}, {unique: true})`.
+///
+/// # Examples
+/// ```no_run
+/// # async fn run() -> leaves::Result<()> {
+/// use leaves::dao::{MongoLeafDao, MongoLeafDaoOptions};
+/// use leaves::LeafDao;
+///
+/// let client = mongodb::Client::with_uri_str("mongodb://localhost/?replicaSet=rs0").await?;
+/// let collection = client.database("leaves").collection("leaf_alloc");
+/// let dao = MongoLeafDao::with_options(collection, MongoLeafDaoOptions::default());
+/// let leaf = dao.update_max(1).await?;
+/// # Ok(())
+/// # }
+/// ```
 pub struct MongoLeafDao {
     collection: Collection,
+    options: MongoLeafDaoOptions,
+}
+
+/// Consistency of [`MongoLeafDao`], `None` leaves it to the collection.
+///
+/// The default is durable on a replica set: a write is acknowledged once it's journaled
+/// on a majority, so it's never rolled back when the primary steps down,
+/// and leaves are read from the primary with read concern `majority`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MongoLeafDaoOptions {
+    /// default is `{ w: "majority", j: true }`.
+    pub write_concern: Option<WriteConcern>,
+    /// default is `majority`.
+    pub read_concern: Option<ReadConcern>,
+    /// default is `primary`.
+    pub read_preference: Option<ReadPreference>,
+}
+
+impl Default for MongoLeafDaoOptions {
+    fn default() -> Self {
+        Self {
+            write_concern: Some(
+                WriteConcern::builder()
+                    .w(Acknowledgment::Majority)
+                    .journal(true)
+                    .build(),
+            ),
+            read_concern: Some(ReadConcern::majority()),
+            read_preference: Some(ReadPreference::Primary),
+        }
+    }
+}
+
+impl MongoLeafDaoOptions {
+    pub fn set_write_concern(mut self, write_concern: WriteConcern) -> Self {
+        self.write_concern = Some(write_concern);
+        self
+    }
+
+    pub fn set_read_concern(mut self, read_concern: ReadConcern) -> Self {
+        self.read_concern = Some(read_concern);
+        self
+    }
+
+    pub fn set_read_preference(mut self, read_preference: ReadPreference) -> Self {
+        self.read_preference = Some(read_preference);
+        self
+    }
+
+    /// The defaults, except what is set in the connection string.
+    pub fn from_client_options(options: &ClientOptions) -> Self {
+        let defaults = Self::default();
+        Self {
+            write_concern: options.write_concern.clone().or(defaults.write_concern),
+            read_concern: options.read_concern.clone().or(defaults.read_concern),
+            read_preference: match &options.selection_criteria {
+                Some(SelectionCriteria::ReadPreference(preference)) => Some(preference.clone()),
+                _ => defaults.read_preference,
+            },
+        }
+    }
+
+    fn selection_criteria(&self) -> Option<SelectionCriteria> {
+        self.read_preference
+            .clone()
+            .map(SelectionCriteria::ReadPreference)
+    }
 }
 
 #[async_trait]
 impl LeafDao for MongoLeafDao {
     async fn leaves(&self) -> Result<Vec<Leaf>> {
-        Ok(self
+        let options = FindOptions::builder()
+            .read_concern(self.options.read_concern.clone())
+            .selection_criteria(self.options.selection_criteria())
+            .build();
+        let docs = self
             .collection
-            .find(None, None)
+            .find(None, options)
             .await?
-            .collect::<Vec<_>>()
-            .await
-            .into_iter()
-            .filter_map(mongodb::error::Result::ok)
-            .filter_map(|doc| bson::from_bson(doc.into()).ok())
-            .collect())
+            .try_collect::<Vec<_>>()
+            .await?;
+        docs.into_iter()
+            .map(|doc| Ok(bson::from_bson(doc.into())?))
+            .collect()
     }
 
     async fn leaf(&self, tag: i32) -> Result<Leaf> {
         let filter = bson::doc! {
           "tag": tag
         };
-        self.collection
-            .find_one(filter, None)
-            .await?
-            .and_then(|doc| bson::from_bson(doc.into()).ok())
-            .ok_or(Error::TagNotExist)
+        let options = FindOneOptions::builder()
+            .read_concern(self.options.read_concern.clone())
+            .selection_criteria(self.options.selection_criteria())
+            .build();
+        let doc = self.collection.find_one(filter, options).await?;
+        Ok(bson::from_bson(doc.ok_or(Error::TagNotExist)?.into())?)
     }
 
     async fn insert(&self, leaf: Leaf) -> Result<()> {
+        let filter = bson::doc! {
+            "tag": leaf.tag
+        };
         let doc = bson::to_bson(&leaf)?
             .as_document()
             .ok_or(Error::SerializationError)?
             .clone();
-        self.collection.insert_one(doc, None).await?;
-        Ok(())
+        // only written if no document of the tag matches
+        let update = bson::doc! {
+            "$setOnInsert": doc
+        };
+        let options = UpdateOptions::builder()
+            .upsert(true)
+            .write_concern(self.options.write_concern.clone())
+            .build();
+        match self.collection.update_one(filter, update, options).await {
+            Ok(result) if result.upserted_id.is_none() => Err(Error::TagExists),
+            Ok(_) => Ok(()),
+            Err(err) if is_duplicate_key(&err) => Err(Error::TagExists),
+            Err(err) => Err(err.into()),
+        }
     }
 
     async fn tags(&self) -> Result<Vec<i32>> {
         let projection = bson::doc! {
             "tag" :1
         };
-        let options = mongodb::options::FindOptions::builder()
+        let options = FindOptions::builder()
             .projection(projection)
+            .read_concern(self.options.read_concern.clone())
+            .selection_criteria(self.options.selection_criteria())
             .build();
-        Ok(self
+        let docs = self
             .collection
             .find(None, options)
             .await?
-            .collect::<Vec<_>>()
-            .await
-            .into_iter()
-            .filter_map(mongodb::error::Result::ok)
-            .filter_map(|doc| doc.get_i32("tag").ok())
-            .collect())
+            .try_collect::<Vec<_>>()
+            .await?;
+        docs.into_iter()
+            .map(|doc| doc.get_i32("tag").map_err(|_| Error::SerializationError))
+            .collect()
     }
 
     async fn update_max(&self, tag: i32) -> Result<Leaf> {
@@ -69,27 +184,129 @@ impl LeafDao for MongoLeafDao {
     }
 
     async fn update_max_by_step(&self, tag: i32, step: i32) -> Result<Leaf> {
+        let update = bson::doc! {
+            "$inc": {
+                "max_id": step
+            }
+        };
+        self.update_leaf(tag, update).await
+    }
+
+    async fn update_max_by_step_up_to(&self, tag: i32, step: i32, max_value: i64) -> Result<Leaf> {
         let filter = bson::doc! {
-            "tag": tag
+            "tag": tag,
+            "max_id": {
+                "$lte": max_value
+            }
         };
         let update = bson::doc! {
             "$inc": {
                 "max_id": step
             }
         };
-        let options = mongodb::options::FindOneAndUpdateOptions::builder()
-            .return_document(mongodb::options::ReturnDocument::After)
+        match self.find_one_and_update(filter, update).await? {
+            Some(doc) => Ok(bson::from_bson(doc.into())?),
+            // a missing tag fails here, rather than being taken as exhausted
+            None => self.leaf(tag).await.and(Err(Error::TagExhausted)),
+        }
+    }
+
+    async fn update_step(&self, tag: i32, step: i32) -> Result<Leaf> {
+        let update = bson::doc! {
+            "$set": {
+                "step": step
+            }
+        };
+        self.update_leaf(tag, update).await
+    }
+
+    async fn delete(&self, tag: i32) -> Result<()> {
+        let filter = bson::doc! {
+            "tag": tag
+        };
+        let options = DeleteOptions::builder()
+            .write_concern(self.options.write_concern.clone())
             .build();
-        self.collection
-            .find_one_and_update(filter, update, options)
-            .await?
-            .and_then(|doc| bson::from_bson(doc.into()).ok())
-            .ok_or(Error::TagNotExist)
+        let result = self.collection.delete_one(filter, options).await?;
+        if result.deleted_count == 0 {
+            return Err(Error::TagNotExist);
+        }
+        Ok(())
     }
+
+    async fn set_max_id_if_greater(&self, tag: i32, max_id: i64) -> Result<Leaf> {
+        let update = bson::doc! {
+            "$max": {
+                "max_id": max_id
+            }
+        };
+        self.update_leaf(tag, update).await
+    }
+
+    async fn upsert(&self, leaf: Leaf) -> Result<()> {
+        let filter = bson::doc! {
+            "tag": leaf.tag
+        };
+        let doc = bson::to_bson(&leaf)?
+            .as_document()
+            .ok_or(Error::SerializationError)?
+            .clone();
+        let options = ReplaceOptions::builder()
+            .upsert(true)
+            .write_concern(self.options.write_concern.clone())
+            .build();
+        self.collection.replace_one(filter, doc, options).await?;
+        Ok(())
+    }
+}
+
+/// Concurrent inserts of a tag are only told apart by a unique index on `tag`.
+fn is_duplicate_key(err: &mongodb::error::Error) -> bool {
+    matches!(
+        &*err.kind,
+        ErrorKind::WriteError(WriteFailure::WriteError(err)) if err.code == DUPLICATE_KEY
+    )
 }
 
 impl MongoLeafDao {
+    /// Use [`MongoLeafDaoOptions::default`].
     pub fn new(collection: Collection) -> Self {
-        Self { collection }
+        Self::with_options(collection, MongoLeafDaoOptions::default())
+    }
+
+    pub fn with_options(collection: Collection, options: MongoLeafDaoOptions) -> Self {
+        Self {
+            collection,
+            options,
+        }
+    }
+
+    pub fn options(&self) -> &MongoLeafDaoOptions {
+        &self.options
+    }
+
+    /// Apply `update` to a leaf and return the updated one.
+    async fn update_leaf(&self, tag: i32, update: bson::Document) -> Result<Leaf> {
+        let filter = bson::doc! {
+            "tag": tag
+        };
+        let doc = self.find_one_and_update(filter, update).await?;
+        Ok(bson::from_bson(doc.ok_or(Error::TagNotExist)?.into())?)
+    }
+
+    /// Apply `update` to the leaf matching `filter` and return the updated document.
+    async fn find_one_and_update(
+        &self,
+        filter: bson::Document,
+        update: bson::Document,
+    ) -> Result<Option<bson::Document>> {
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .write_concern(self.options.write_concern.clone())
+            .build();
+        Ok(self
+            .collection
+            .find_one_and_update(filter, update, options)
+            .await?)
     }
 }
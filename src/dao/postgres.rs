@@ -1,9 +1,8 @@
 use sqlx::postgres::{PgPool, PgQueryAs};
-use sqlx::row::Row;
 
 use async_trait::async_trait;
 
-use crate::{Leaf, LeafDao, Result};
+use crate::{Error, FreeRange, Leaf, LeafDao, Result};
 
 pub struct PgLeafDao {
     pool: PgPool,
@@ -47,21 +46,135 @@ impl LeafDao for PgLeafDao {
 
     async fn update_max(&self, tag: i32) -> Result<Leaf> {
         let mut conn = self.pool.acquire().await?;
-        sqlx::query("UPDATE leaf_alloc SET max_id = max_id + step WHERE tag = ?")
+        // returned by the update itself, or a concurrent increment could be read instead
+        let leaf: Option<Leaf> = sqlx::query_as(
+            "UPDATE leaf_alloc SET max_id = max_id + step WHERE tag = $1 \
+             RETURNING tag, max_id, step",
+        )
+        .bind(tag)
+        .fetch_optional(&mut conn)
+        .await?;
+        leaf.ok_or(Error::TagNotExist)
+    }
+
+    async fn update_max_by_step(&self, tag: i32, step: i32) -> Result<Leaf> {
+        let mut conn = self.pool.acquire().await?;
+        let leaf: Option<Leaf> = sqlx::query_as(
+            "UPDATE leaf_alloc SET max_id = max_id + $1 WHERE tag = $2 \
+             RETURNING tag, max_id, step",
+        )
+        .bind(step)
+        .bind(tag)
+        .fetch_optional(&mut conn)
+        .await?;
+        leaf.ok_or(Error::TagNotExist)
+    }
+
+    async fn update_max_by_step_up_to(&self, tag: i32, step: i32, max_value: i64) -> Result<Leaf> {
+        let mut conn = self.pool.acquire().await?;
+        let leaf: Option<Leaf> = sqlx::query_as(
+            "UPDATE leaf_alloc SET max_id = max_id + $1 WHERE tag = $2 AND max_id <= $3 \
+             RETURNING tag, max_id, step",
+        )
+        .bind(step)
+        .bind(tag)
+        .bind(max_value)
+        .fetch_optional(&mut conn)
+        .await?;
+        drop(conn);
+        match leaf {
+            Some(leaf) => Ok(leaf),
+            // a missing tag fails here, rather than being taken as exhausted
+            None => self.leaf(tag).await.and(Err(Error::TagExhausted)),
+        }
+    }
+
+    async fn update_step(&self, tag: i32, step: i32) -> Result<Leaf> {
+        let mut conn = self.pool.acquire().await?;
+        let leaf: Option<Leaf> = sqlx::query_as(
+            "UPDATE leaf_alloc SET step = $1 WHERE tag = $2 RETURNING tag, max_id, step",
+        )
+        .bind(step)
+        .bind(tag)
+        .fetch_optional(&mut conn)
+        .await?;
+        leaf.ok_or(Error::TagNotExist)
+    }
+
+    async fn delete(&self, tag: i32) -> Result<()> {
+        let mut conn = self.pool.acquire().await?;
+        let rows = sqlx::query("DELETE FROM leaf_alloc WHERE tag = $1")
             .bind(tag)
             .execute(&mut conn)
             .await?;
-        self.get_leaf(tag).await
+        if rows == 0 {
+            return Err(Error::TagNotExist);
+        }
+        Ok(())
     }
 
-    async fn update_max_by_step(&self, tag: i32, step: i32) -> Result<Leaf> {
+    async fn set_max_id_if_greater(&self, tag: i32, max_id: i64) -> Result<Leaf> {
         let mut conn = self.pool.acquire().await?;
-        sqlx::query("UPDATE leaf_alloc SET max_id = max_id + ? WHERE tag = ?")
-            .bind(step)
-            .bind(tag)
+        // updated unconditionally, so the leaf is returned even if `max_id` isn't moved
+        let leaf: Option<Leaf> = sqlx::query_as(
+            "UPDATE leaf_alloc SET max_id = GREATEST(max_id, $1) WHERE tag = $2 \
+             RETURNING tag, max_id, step",
+        )
+        .bind(max_id)
+        .bind(tag)
+        .fetch_optional(&mut conn)
+        .await?;
+        leaf.ok_or(Error::TagNotExist)
+    }
+
+    async fn upsert(&self, leaf: Leaf) -> Result<()> {
+        let mut conn = self.pool.acquire().await?;
+        sqlx::query(
+            "INSERT INTO leaf_alloc (tag, max_id, step) VALUES ($1, $2, $3) \
+             ON CONFLICT (tag) DO UPDATE SET max_id = EXCLUDED.max_id, step = EXCLUDED.step",
+        )
+        .bind(leaf.tag)
+        .bind(leaf.max_id)
+        .bind(leaf.step)
+        .execute(&mut conn)
+        .await?;
+        Ok(())
+    }
+
+    async fn push_free_range(&self, range: FreeRange) -> Result<()> {
+        let mut conn = self.pool.acquire().await?;
+        sqlx::query("INSERT INTO leaf_free_ranges (tag, start_id, end_id) VALUES ($1, $2, $3)")
+            .bind(range.tag)
+            .bind(range.start)
+            .bind(range.end)
             .execute(&mut conn)
             .await?;
-        self.get_leaf(tag).await
+        Ok(())
+    }
+
+    async fn pop_free_range(&self, tag: i32) -> Result<Option<FreeRange>> {
+        let mut conn = self.pool.acquire().await?;
+        loop {
+            let row: Option<(i64, i64)> = sqlx::query_as(
+                "SELECT start_id, end_id FROM leaf_free_ranges WHERE tag = $1 ORDER BY start_id LIMIT 1",
+            )
+            .bind(tag)
+            .fetch_optional(&mut conn)
+            .await?;
+            let (start, end) = match row {
+                Some(row) => row,
+                None => return Ok(None),
+            };
+            let rows = sqlx::query("DELETE FROM leaf_free_ranges WHERE tag = $1 AND start_id = $2")
+                .bind(tag)
+                .bind(start)
+                .execute(&mut conn)
+                .await?;
+            // taken by another instance in between otherwise
+            if rows > 0 {
+                return Ok(Some(FreeRange { tag, start, end }));
+            }
+        }
     }
 }
 
@@ -83,6 +196,16 @@ impl PgLeafDao {
         )
         .execute(&mut conn)
         .await?;
+        sqlx::query(
+            r#"CREATE TABLE leaf_free_ranges (
+                    tag INT NOT NULL,
+                    start_id BIGINT NOT NULL,
+                    end_id BIGINT NOT NULL,
+                    PRIMARY KEY (tag, start_id)
+                )"#,
+        )
+        .execute(&mut conn)
+        .await?;
         Ok(())
     }
 }
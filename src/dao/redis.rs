@@ -1,16 +1,144 @@
-// use futures_util::StreamExt;
+//! Redis backend, on a single node, behind Sentinel, or on a Redis Cluster.
+//!
+//! # Failover
+//! Redis replicates asynchronously, so increments of `max_id` acknowledged by a master
+//! but not yet replicated are lost when a replica is promoted. The new master then
+//! hands out those ranges again: every range allocated within the replication lag
+//! before the failover may be issued twice, ranges allocated earlier are safe.
+//! Bump affected tags past the old values with
+//! [`set_max_id_if_greater`](crate::LeafDao::set_max_id_if_greater) after a failover.
+//!
+//! * Sentinel: the master is looked up from the sentinels, and again when the master
+//!   stops answering or turns into a replica, the failed request is retried once.
+//!   A request the master didn't answer within the timeout may have been applied,
+//!   at worst a range is skipped then, it's never issued twice by the retry.
+//! * Cluster: requests are routed by slot, `MOVED` and `ASK` redirections are followed
+//!   and the slot map is refreshed. Keys are named `leaf_alloc:{<tag>}`, so everything
+//!   of a tag hashes to one slot.
+//!
+//! # Durability
+//! With [`RedisDao::wait_for_replicas`], every change of a leaf, from `insert` to `delete`,
+//! is made by a script followed by `WAIT` on the same connection,
+//! and a leaf is only returned once enough replicas have it, so a promoted replica
+//! never hands out a range again. A failed `WAIT` leaves the change applied on the master,
+//! the range is skipped then. Replicas and the master lose what they hold in memory
+//! when they all restart though, see [`RedisDao::check_persistence`].
+use std::collections::{HashMap, HashSet};
 use std::convert::{TryFrom, TryInto};
+use std::future::Future;
+use std::io;
+use std::sync::RwLock;
+use std::time::Duration;
 
-use darkredis::{Command, ConnectionPool, Value};
+use async_mutex::Mutex;
+use darkredis::{Command, Connection, ConnectionPool, Value};
 
 use async_trait::async_trait;
 
-use crate::{Error, Leaf, LeafDao, Result};
+use crate::{utils, Error, Leaf, LeafDao, Result};
+
+/// Writes a leaf of `tag` ARGV[1], `max_id` ARGV[2] and `step` ARGV[3], returns -1 without
+/// writing if it exists already and ARGV[4] isn't `overwrite`, or 1.
+const PUT_SCRIPT: &str = r#"
+if ARGV[4] ~= 'overwrite' and redis.call('EXISTS', KEYS[1]) == 1 then return -1 end
+redis.call('HMSET', KEYS[1], 'tag', ARGV[1], 'max_id', ARGV[2], 'step', ARGV[3])
+return 1
+"#;
+
+/// Moves `max_id` of an existing leaf by ARGV[1], or by its `step` if ARGV[1] is empty,
+/// returns the leaf, or 0 if the leaf doesn't exist.
+const UPDATE_MAX_SCRIPT: &str = r#"
+if redis.call('EXISTS', KEYS[1]) == 0 then return 0 end
+local step = ARGV[1]
+if step == '' then step = redis.call('HGET', KEYS[1], 'step') end
+redis.call('HINCRBY', KEYS[1], 'max_id', step)
+return redis.call('HMGET', KEYS[1], 'tag', 'max_id', 'step')
+"#;
+
+/// Moves `max_id` of an existing leaf by ARGV[1] unless it's greater than ARGV[2] already,
+/// returns the leaf, 0 if the leaf doesn't exist, or -1 if `max_id` is past ARGV[2].
+const UPDATE_MAX_UP_TO_SCRIPT: &str = r#"
+if redis.call('EXISTS', KEYS[1]) == 0 then return 0 end
+if tonumber(redis.call('HGET', KEYS[1], 'max_id')) > tonumber(ARGV[2]) then return -1 end
+redis.call('HINCRBY', KEYS[1], 'max_id', ARGV[1])
+return redis.call('HMGET', KEYS[1], 'tag', 'max_id', 'step')
+"#;
+
+/// Deletes a leaf, returns 0 if it doesn't exist.
+const DELETE_SCRIPT: &str = r#"
+return redis.call('DEL', KEYS[1])
+"#;
+
+/// Sets `step` of an existing leaf, returns 0 if the leaf doesn't exist.
+const UPDATE_STEP_SCRIPT: &str = r#"
+if redis.call('EXISTS', KEYS[1]) == 0 then return 0 end
+redis.call('HSET', KEYS[1], 'step', ARGV[1])
+return 1
+"#;
+
+/// Raises `max_id` of an existing leaf, returns 0 if the leaf doesn't exist.
+const SET_MAX_ID_IF_GREATER_SCRIPT: &str = r#"
+if redis.call('EXISTS', KEYS[1]) == 0 then return 0 end
+if tonumber(ARGV[1]) > tonumber(redis.call('HGET', KEYS[1], 'max_id')) then
+    redis.call('HSET', KEYS[1], 'max_id', ARGV[1])
+end
+return 1
+"#;
+
+/// Default of [`RedisDao::with_timeout`].
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Redirections followed for a single request on a cluster.
+const MAX_REDIRECTIONS: usize = 5;
+
+const SLOTS: u16 = 16384;
 
 /// Each leaf will be a hashmap with a key like `leaf_alloc:*`.
 #[derive(Debug)]
 pub struct RedisDao {
-    pool: darkredis::ConnectionPool,
+    topology: Topology,
+    timeout: Duration,
+    wait: Option<Wait>,
+}
+
+/// Replicas to acknowledge a change, see [`RedisDao::wait_for_replicas`].
+#[derive(Debug, Copy, Clone)]
+struct Wait {
+    replicas: usize,
+    timeout: Duration,
+}
+
+#[derive(Debug)]
+enum Topology {
+    Standalone(Standalone),
+    Sentinel(Sentinel),
+    Cluster(Cluster),
+}
+
+#[derive(Debug)]
+struct Standalone {
+    address: String,
+    password: Option<String>,
+    /// dropped on a failure and created again on the next request
+    pool: Mutex<Option<ConnectionPool>>,
+}
+
+#[derive(Debug)]
+struct Sentinel {
+    sentinels: Vec<String>,
+    master_name: String,
+    password: Option<String>,
+    /// dropped on a failure and looked up again on the next request
+    master: RwLock<Option<ConnectionPool>>,
+}
+
+#[derive(Debug)]
+struct Cluster {
+    seeds: Vec<String>,
+    password: Option<String>,
+    /// `(first slot, last slot, master address)`
+    slots: RwLock<Vec<(u16, u16, String)>>,
+    pools: Mutex<HashMap<String, ConnectionPool>>,
 }
 
 impl TryFrom<Value> for Leaf {
@@ -41,6 +169,380 @@ impl TryFrom<Value> for Leaf {
     }
 }
 
+/// Hash slot of `key` on a Redis Cluster, only the part in `{}` counts if there's one.
+pub fn key_slot(key: &[u8]) -> u16 {
+    let hashed = key
+        .iter()
+        .position(|&b| b == b'{')
+        .and_then(|open| {
+            let len = key[open + 1..].iter().position(|&b| b == b'}')?;
+            Some(&key[open + 1..open + 1 + len]).filter(|tag| !tag.is_empty())
+        })
+        .unwrap_or(key);
+    crc16(hashed) % SLOTS
+}
+
+/// CRC16/XMODEM, as used by Redis Cluster.
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+fn value_to_string(value: Value) -> Option<String> {
+    match value {
+        Value::String(s) => String::from_utf8(s).ok(),
+        Value::Integer(i) => Some(i.to_string()),
+        _ => None,
+    }
+}
+
+/// A node that went away may never answer, as darkredis waits on a closed connection
+/// forever, so requests are given up after `timeout`, which counts as a failover.
+/// The pool of the node is dropped then: the reply given up on may still arrive,
+/// and would be read as the reply of the next command on the connection.
+async fn within<T>(timeout: Duration, future: impl Future<Output = Result<T>>) -> Result<T> {
+    utils::timeout(timeout, future).await.unwrap_or_else(|| {
+        let err = io::Error::new(io::ErrorKind::TimedOut, "request timed out");
+        Err(darkredis::Error::Io(err).into())
+    })
+}
+
+/// A connection pool per node, as many connections as cpus.
+async fn create_pool(
+    address: &str,
+    password: Option<&str>,
+    timeout: Duration,
+) -> Result<ConnectionPool> {
+    let pool = ConnectionPool::create(address.into(), password, num_cpus::get());
+    within(timeout, async { Ok(pool.await?) }).await
+}
+
+/// The node can't be reached anymore, or is not the master it used to be.
+fn is_failover(err: &darkredis::Error) -> bool {
+    match err {
+        darkredis::Error::Io(_) | darkredis::Error::ConnectionFailed(_) => true,
+        darkredis::Error::RedisError(msg) => msg.starts_with("READONLY"),
+        _ => false,
+    }
+}
+
+/// Parse a `MOVED <slot> <address>` or `ASK <slot> <address>` error.
+fn redirection(err: &darkredis::Error) -> Option<(bool, String)> {
+    if let darkredis::Error::RedisError(msg) = err {
+        let mut parts = msg.trim_start_matches('-').trim_end().split(' ');
+        let ask = match parts.next()? {
+            "MOVED" => false,
+            "ASK" => true,
+            _ => return None,
+        };
+        return Some((ask, parts.nth(1)?.to_string()));
+    }
+    None
+}
+
+async fn run_on(conn: &mut Connection, commands: &[Command<'_>]) -> Result<Vec<Value>> {
+    let mut values = Vec::with_capacity(commands.len());
+    for command in commands {
+        values.push(conn.run_command(command.clone()).await?);
+    }
+    Ok(values)
+}
+
+async fn run_on_pool(
+    pool: &ConnectionPool,
+    commands: &[Command<'_>],
+    timeout: Duration,
+) -> Result<Vec<Value>> {
+    within(timeout, async {
+        let mut conn = pool.get().await;
+        run_on(&mut conn, commands).await
+    })
+    .await
+}
+
+impl Standalone {
+    async fn pool(&self, timeout: Duration) -> Result<ConnectionPool> {
+        let mut pool = self.pool.lock().await;
+        if let Some(pool) = &*pool {
+            return Ok(pool.clone());
+        }
+        let created = create_pool(&self.address, self.password.as_deref(), timeout).await?;
+        *pool = Some(created.clone());
+        Ok(created)
+    }
+
+    async fn run(&self, commands: &[Command<'_>], timeout: Duration) -> Result<Vec<Value>> {
+        let result = run_on_pool(&self.pool(timeout).await?, commands, timeout).await;
+        if let Err(Error::Redis(err)) = &result {
+            if is_failover(err) {
+                self.pool.lock().await.take();
+            }
+        }
+        result
+    }
+}
+
+impl Sentinel {
+    /// Ask the sentinels in turn for the master, and check it's a master indeed.
+    async fn discover(
+        sentinels: &[String],
+        master_name: &str,
+        password: Option<&str>,
+        timeout: Duration,
+    ) -> Result<ConnectionPool> {
+        let mut last_err = None;
+        for sentinel in sentinels {
+            match within(timeout, Self::ask(sentinel, master_name, password, timeout)).await {
+                Ok(pool) => return Ok(pool),
+                Err(err) => {
+                    tracing::warn!("sentinel {}: {}", sentinel, err);
+                    last_err = Some(err);
+                }
+            }
+        }
+        Err(last_err.unwrap_or_else(|| Error::InvalidUrl("no sentinel given".into())))
+    }
+
+    async fn ask(
+        sentinel: &str,
+        master_name: &str,
+        password: Option<&str>,
+        timeout: Duration,
+    ) -> Result<ConnectionPool> {
+        let mut conn = Connection::connect(sentinel).await?;
+        let command = Command::new("SENTINEL")
+            .arg(b"get-master-addr-by-name")
+            .arg(&master_name);
+        let mut reply = conn
+            .run_command(command)
+            .await?
+            .optional_array()
+            .ok_or_else(|| Error::Remote(format!("unknown master `{}`", master_name)))?
+            .into_iter();
+        let (host, port) = reply
+            .next()
+            .and_then(value_to_string)
+            .zip(reply.next().and_then(value_to_string))
+            .ok_or(Error::SerializationError)?;
+        let address = format!("{}:{}", host, port);
+        let pool = create_pool(&address, password, timeout).await?;
+        let role = run_on_pool(&pool, &[Command::new("ROLE")], timeout)
+            .await?
+            .pop();
+        match role
+            .and_then(Value::optional_array)
+            .and_then(|role| role.into_iter().next())
+            .and_then(value_to_string)
+        {
+            Some(role) if role == "master" => {
+                tracing::info!("master `{}` is at {}", master_name, address);
+                Ok(pool)
+            }
+            _ => Err(Error::Remote(format!("{} is not a master", address))),
+        }
+    }
+
+    async fn rediscover(&self, timeout: Duration) -> Result<ConnectionPool> {
+        let pool = Self::discover(
+            &self.sentinels,
+            &self.master_name,
+            self.password.as_deref(),
+            timeout,
+        )
+        .await?;
+        *self.master.write().unwrap() = Some(pool.clone());
+        Ok(pool)
+    }
+
+    async fn run_on_master(
+        &self,
+        pool: &ConnectionPool,
+        commands: &[Command<'_>],
+        timeout: Duration,
+    ) -> Result<Vec<Value>> {
+        let result = run_on_pool(pool, commands, timeout).await;
+        if let Err(Error::Redis(err)) = &result {
+            if is_failover(err) {
+                self.master.write().unwrap().take();
+            }
+        }
+        result
+    }
+
+    async fn run(&self, commands: &[Command<'_>], timeout: Duration) -> Result<Vec<Value>> {
+        let master = self.master.read().unwrap().clone();
+        let pool = match master {
+            Some(pool) => pool,
+            None => self.rediscover(timeout).await?,
+        };
+        match self.run_on_master(&pool, commands, timeout).await {
+            Err(Error::Redis(err)) if is_failover(&err) => {
+                tracing::warn!(
+                    "master `{}` failed: {}, rediscovering",
+                    self.master_name,
+                    err
+                );
+                let pool = self.rediscover(timeout).await?;
+                self.run_on_master(&pool, commands, timeout).await
+            }
+            result => result,
+        }
+    }
+}
+
+impl Cluster {
+    /// Load the slot map from any node reachable, seeds first.
+    async fn refresh(&self, timeout: Duration) -> Result<()> {
+        let mut nodes = self.seeds.clone();
+        nodes.extend(self.masters());
+        let mut last_err = None;
+        for node in nodes {
+            match self.load_slots(&node, timeout).await {
+                Ok(slots) => {
+                    *self.slots.write().unwrap() = slots;
+                    return Ok(());
+                }
+                Err(err) => last_err = Some(err),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| Error::InvalidUrl("no cluster node given".into())))
+    }
+
+    async fn load_slots(&self, node: &str, timeout: Duration) -> Result<Vec<(u16, u16, String)>> {
+        let reply = self
+            .run_on_node(node, &[Command::new("CLUSTER").arg(b"SLOTS")], timeout)
+            .await?
+            .pop()
+            .and_then(Value::optional_array)
+            .ok_or(Error::SerializationError)?;
+        reply
+            .into_iter()
+            .map(|range| {
+                let mut range = range.optional_array()?.into_iter();
+                let start = range.next()?.optional_integer()? as u16;
+                let end = range.next()?.optional_integer()? as u16;
+                let mut master = range.next()?.optional_array()?.into_iter();
+                let host = master.next().and_then(value_to_string)?;
+                let port = master.next().and_then(value_to_string)?;
+                Some((start, end, format!("{}:{}", host, port)))
+            })
+            .collect::<Option<_>>()
+            .ok_or(Error::SerializationError)
+    }
+
+    fn masters(&self) -> Vec<String> {
+        let mut masters = self
+            .slots
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(_, _, node)| node.clone())
+            .collect::<Vec<_>>();
+        masters.sort();
+        masters.dedup();
+        masters
+    }
+
+    fn node_of(&self, key: &[u8]) -> Option<String> {
+        let slot = key_slot(key);
+        self.slots
+            .read()
+            .unwrap()
+            .iter()
+            .find(|(start, end, _)| (*start..=*end).contains(&slot))
+            .map(|(_, _, node)| node.clone())
+    }
+
+    async fn pool(&self, node: &str, timeout: Duration) -> Result<ConnectionPool> {
+        let mut pools = self.pools.lock().await;
+        if let Some(pool) = pools.get(node) {
+            return Ok(pool.clone());
+        }
+        let pool = create_pool(node, self.password.as_deref(), timeout).await?;
+        pools.insert(node.to_string(), pool.clone());
+        Ok(pool)
+    }
+
+    async fn run_on_node(
+        &self,
+        node: &str,
+        commands: &[Command<'_>],
+        timeout: Duration,
+    ) -> Result<Vec<Value>> {
+        let result = run_on_pool(&self.pool(node, timeout).await?, commands, timeout).await;
+        if let Err(Error::Redis(err)) = &result {
+            if is_failover(err) {
+                // connections of a pool are never reestablished, and may hold a reply given up on
+                self.pools.lock().await.remove(node);
+            }
+        }
+        result
+    }
+
+    async fn run(
+        &self,
+        key: &[u8],
+        commands: &[Command<'_>],
+        timeout: Duration,
+    ) -> Result<Vec<Value>> {
+        let mut node = match self.node_of(key) {
+            Some(node) => node,
+            None => {
+                self.refresh(timeout).await?;
+                self.node_of(key)
+                    .ok_or_else(|| Error::Remote("slot not served".into()))?
+            }
+        };
+        let mut asking = false;
+        for _ in 0..MAX_REDIRECTIONS {
+            let result = if asking {
+                let mut asked = vec![Command::new("ASKING")];
+                asked.extend_from_slice(commands);
+                self.run_on_node(&node, &asked, timeout)
+                    .await
+                    .map(|values| values.into_iter().skip(1).collect())
+            } else {
+                self.run_on_node(&node, commands, timeout).await
+            };
+            match result {
+                Err(Error::Redis(err)) => {
+                    if let Some((ask, target)) = redirection(&err) {
+                        if !ask {
+                            self.refresh(timeout).await?;
+                        }
+                        node = target;
+                        asking = ask;
+                    } else if is_failover(&err) {
+                        tracing::warn!("node {} failed: {}, refreshing slots", node, err);
+                        self.refresh(timeout).await?;
+                        node = self
+                            .node_of(key)
+                            .ok_or_else(|| Error::Remote("slot not served".into()))?;
+                        asking = false;
+                    } else {
+                        return Err(err.into());
+                    }
+                }
+                result => return result,
+            }
+        }
+        Err(Error::Remote(format!(
+            "too many redirections for {}",
+            String::from_utf8_lossy(key)
+        )))
+    }
+}
+
 #[async_trait]
 impl LeafDao for RedisDao {
     async fn leaves(&self) -> Result<Vec<Leaf>> {
@@ -54,73 +556,310 @@ impl LeafDao for RedisDao {
     }
 
     async fn leaf(&self, tag: i32) -> Result<Leaf> {
-        let mut conn = self.pool.get().await;
-        let key = format!("leaf_alloc:{}", tag);
+        let key = self.key(tag);
         let command = Command::new("HMGET")
             .arg(&key)
             .arg(b"tag")
             .arg(b"max_id")
             .arg(b"step");
-        conn.run_command(command).await?.try_into()
+        self.run(&key, command).await?.try_into()
     }
 
     async fn insert(&self, leaf: Leaf) -> Result<()> {
-        let mut conn = self.pool.get().await;
-        let key = format!("leaf_alloc:{}", leaf.tag).into_bytes();
-        let tag_bytes = leaf.tag.to_string().into_bytes();
-        let max_id_bytes = leaf.max_id.to_string().into_bytes();
-        let step_bytes = leaf.step.to_string().into_bytes();
-        let command = Command::new("HMSET")
-            .arg(&key)
-            .arg(b"tag")
-            .arg(&tag_bytes)
-            .arg(b"max_id")
-            .arg(&max_id_bytes)
-            .arg(b"step")
-            .arg(&step_bytes);
-        conn.run_command(command).await?;
-        Ok(())
+        self.put(leaf, false).await
     }
 
     async fn tags(&self) -> Result<Vec<i32>> {
-        let mut conn = self.pool.get().await;
         let command = Command::new("KEYS").arg(b"leaf_alloc:*");
-        Ok(conn
-            .run_command(command)
+        let tags = self
+            .run_on_masters(command)
             .await?
-            .optional_array()
-            .map(|tags| {
-                tags.into_iter()
-                    .map(|v| {
-                        v.optional_string()
-                            .and_then(|v| {
-                                String::from_utf8(v.rsplit(|i| i.eq(&b':')).next()?.into()).ok()
-                            })
-                            .and_then(|s| s.parse::<i32>().ok())
-                    })
-                    .flatten()
-                    .collect()
+            .into_iter()
+            .map(|(_, keys)| keys)
+            .filter_map(Value::optional_array)
+            .flatten()
+            .filter_map(|v| {
+                v.optional_string()
+                    .and_then(|v| String::from_utf8(v.rsplit(|i| i.eq(&b':')).next()?.into()).ok())
+                    .and_then(|s| s.trim_matches(|c| c == '{' || c == '}').parse::<i32>().ok())
             })
-            .unwrap_or(vec![]))
+            .collect::<HashSet<_>>();
+        Ok(tags.into_iter().collect())
     }
 
     async fn update_max(&self, tag: i32) -> Result<Leaf> {
-        let step = self.leaf(tag).await.map(|l| l.step).unwrap_or(1000);
-        self.update_max_by_step(tag, step).await
+        self.eval_on_leaf(UPDATE_MAX_SCRIPT, tag, &[String::new()])
+            .await?
+            .try_into()
     }
 
     async fn update_max_by_step(&self, tag: i32, step: i32) -> Result<Leaf> {
-        let mut conn = self.pool.get().await;
-        conn.hincrby(format!("leaf_alloc:{}", tag), b"max_id", step as isize)
+        // incremented and read at once, or a concurrent increment could be read instead
+        self.eval_on_leaf(UPDATE_MAX_SCRIPT, tag, &[step.to_string()])
+            .await?
+            .try_into()
+    }
+
+    async fn update_max_by_step_up_to(&self, tag: i32, step: i32, max_value: i64) -> Result<Leaf> {
+        let args = [step.to_string(), max_value.to_string()];
+        match self
+            .eval_on_leaf(UPDATE_MAX_UP_TO_SCRIPT, tag, &args)
+            .await?
+        {
+            Value::Integer(-1) => Err(Error::TagExhausted),
+            value => value.try_into(),
+        }
+    }
+
+    async fn update_step(&self, tag: i32, step: i32) -> Result<Leaf> {
+        self.eval_on_leaf(UPDATE_STEP_SCRIPT, tag, &[step.to_string()])
+            .await?;
+        self.leaf(tag).await
+    }
+
+    async fn delete(&self, tag: i32) -> Result<()> {
+        // a script, so it's waited for on replicas like every other change
+        self.eval_on_leaf(DELETE_SCRIPT, tag, &[]).await?;
+        Ok(())
+    }
+
+    async fn set_max_id_if_greater(&self, tag: i32, max_id: i64) -> Result<Leaf> {
+        self.eval_on_leaf(SET_MAX_ID_IF_GREATER_SCRIPT, tag, &[max_id.to_string()])
             .await?;
         self.leaf(tag).await
     }
+
+    async fn upsert(&self, leaf: Leaf) -> Result<()> {
+        self.put(leaf, true).await
+    }
 }
 
 impl RedisDao {
+    /// Key of a leaf, with the tag as hash tag on a cluster.
+    fn key(&self, tag: i32) -> Vec<u8> {
+        match self.topology {
+            Topology::Cluster(_) => format!("leaf_alloc:{{{}}}", tag),
+            _ => format!("leaf_alloc:{}", tag),
+        }
+        .into_bytes()
+    }
+
+    /// Write `leaf`, failing with [`Error::TagExists`] if it exists and not to `overwrite`.
+    async fn put(&self, leaf: Leaf, overwrite: bool) -> Result<()> {
+        let args = [
+            leaf.tag.to_string(),
+            leaf.max_id.to_string(),
+            leaf.step.to_string(),
+            if overwrite { "overwrite" } else { "" }.to_string(),
+        ];
+        match self.eval_on_leaf(PUT_SCRIPT, leaf.tag, &args).await? {
+            Value::Integer(-1) => Err(Error::TagExists),
+            _ => Ok(()),
+        }
+    }
+
+    /// Run a command on the node holding `key`.
+    async fn run(&self, key: &[u8], command: Command<'_>) -> Result<Value> {
+        let mut values = self.run_all(key, &[command], self.timeout).await?;
+        values.pop().ok_or(Error::SerializationError)
+    }
+
+    /// Run `commands` one after another on a connection to the node holding `key`.
+    async fn run_all(
+        &self,
+        key: &[u8],
+        commands: &[Command<'_>],
+        timeout: Duration,
+    ) -> Result<Vec<Value>> {
+        match &self.topology {
+            Topology::Standalone(standalone) => standalone.run(commands, timeout).await,
+            Topology::Sentinel(sentinel) => sentinel.run(commands, timeout).await,
+            Topology::Cluster(cluster) => cluster.run(key, commands, timeout).await,
+        }
+    }
+
+    /// Run a script taking the leaf's key and `args`, which returns 0 if the leaf doesn't exist.
+    /// Its change is waited for on replicas if required.
+    async fn eval_on_leaf(&self, script: &str, tag: i32, args: &[String]) -> Result<Value> {
+        let key = self.key(tag);
+        let mut eval = Command::new("EVAL").arg(&script).arg(b"1").arg(&key);
+        for arg in args {
+            eval = eval.arg(arg);
+        }
+        let mut commands = vec![eval];
+        let (replicas, millis);
+        let mut timeout = self.timeout;
+        if let Some(wait) = self.wait {
+            replicas = wait.replicas.to_string();
+            millis = wait.timeout.as_millis().to_string();
+            // on the same connection, so it waits for the script
+            commands.push(Command::new("WAIT").arg(&replicas).arg(&millis));
+            timeout += wait.timeout;
+        }
+        let mut values = self.run_all(&key, &commands, timeout).await?.into_iter();
+        let value = match values.next().ok_or(Error::SerializationError)? {
+            Value::Integer(0) => return Err(Error::TagNotExist),
+            value => value,
+        };
+        if let Some(wait) = self.wait {
+            let acked = values
+                .next()
+                .and_then(Value::optional_integer)
+                .ok_or(Error::SerializationError)? as usize;
+            if acked < wait.replicas {
+                return Err(Error::NotReplicated(acked, wait.replicas));
+            }
+        }
+        Ok(value)
+    }
+
+    /// Run `command` on every master, replies come with the node they are from.
+    async fn run_on_masters(&self, command: Command<'_>) -> Result<Vec<(String, Value)>> {
+        let commands = [command];
+        let mut values = vec![];
+        match &self.topology {
+            Topology::Standalone(standalone) => {
+                for value in standalone.run(&commands, self.timeout).await? {
+                    values.push(("master".to_string(), value));
+                }
+            }
+            Topology::Sentinel(sentinel) => {
+                for value in sentinel.run(&commands, self.timeout).await? {
+                    values.push((format!("master `{}`", sentinel.master_name), value));
+                }
+            }
+            // every master holds a part of the keys
+            Topology::Cluster(cluster) => {
+                if cluster.masters().is_empty() {
+                    cluster.refresh(self.timeout).await?;
+                }
+                for node in cluster.masters() {
+                    for value in cluster.run_on_node(&node, &commands, self.timeout).await? {
+                        values.push((node.clone(), value));
+                    }
+                }
+            }
+        }
+        Ok(values)
+    }
+
+    /// Check the masters persist every change before answering, i.e. `appendonly yes`
+    /// and `appendfsync always`, or changes acknowledged may be lost when they restart.
+    /// Problems found are logged as warnings and returned, call it at startup.
+    pub async fn check_persistence(&self) -> Result<Vec<String>> {
+        let mut warnings = vec![];
+        let config = Command::new("CONFIG").arg(b"GET").arg(b"append*");
+        for (node, value) in self.run_on_masters(config).await? {
+            let mut values = value
+                .optional_array()
+                .ok_or(Error::SerializationError)?
+                .into_iter()
+                .map(value_to_string);
+            let mut config = HashMap::new();
+            while let (Some(name), Some(value)) = (values.next(), values.next()) {
+                if let (Some(name), Some(value)) = (name, value) {
+                    config.insert(name, value);
+                }
+            }
+            let setting = |name: &str| config.get(name).map(String::as_str).unwrap_or("unknown");
+            if setting("appendonly") != "yes" {
+                warnings.push(format!(
+                    "AOF is disabled on {}, changes since the last snapshot are lost when it restarts",
+                    node
+                ));
+            } else if setting("appendfsync") != "always" {
+                warnings.push(format!(
+                    "appendfsync is `{}` on {}, changes not yet synced are lost when it crashes",
+                    setting("appendfsync"),
+                    node
+                ));
+            }
+        }
+        for warning in &warnings {
+            tracing::warn!("{}, ranges may be handed out twice", warning);
+        }
+        Ok(warnings)
+    }
+
     pub async fn new(address: impl Into<String>, password: Option<&str>) -> Result<Self> {
+        let address = address.into();
+        let pool = create_pool(&address, password, DEFAULT_TIMEOUT).await?;
+        Ok(Self {
+            topology: Topology::Standalone(Standalone {
+                address,
+                password: password.map(String::from),
+                pool: Mutex::new(Some(pool)),
+            }),
+            timeout: DEFAULT_TIMEOUT,
+            wait: None,
+        })
+    }
+
+    /// Give up a request after `timeout`, default is 3 seconds.
+    /// Connections to the node are made anew then, behind sentinel or on a cluster
+    /// the master is looked up again as well.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Return a leaf changed only once at least `replicas` replicas acknowledged the change,
+    /// waiting at most `timeout` for them, otherwise [`Error::NotReplicated`] is returned.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # async fn run() -> leaves::Result<()> {
+    /// use std::time::Duration;
+    /// use leaves::dao::RedisDao;
+    ///
+    /// let dao = RedisDao::sentinel(&["127.0.0.1:26379"], "mymaster", None)
+    ///     .await?
+    ///     .wait_for_replicas(1, Duration::from_millis(100));
+    /// dao.check_persistence().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn wait_for_replicas(mut self, replicas: usize, timeout: Duration) -> Self {
+        self.wait = Some(Wait { replicas, timeout });
+        self
+    }
+
+    /// Connect to the master named `master_name`, which is looked up from `sentinels`.
+    pub async fn sentinel(
+        sentinels: &[impl AsRef<str>],
+        master_name: &str,
+        password: Option<&str>,
+    ) -> Result<Self> {
+        let sentinels = sentinels
+            .iter()
+            .map(|s| s.as_ref().to_string())
+            .collect::<Vec<_>>();
+        let master = Sentinel::discover(&sentinels, master_name, password, DEFAULT_TIMEOUT).await?;
+        Ok(Self {
+            topology: Topology::Sentinel(Sentinel {
+                sentinels,
+                master_name: master_name.to_string(),
+                password: password.map(String::from),
+                master: RwLock::new(Some(master)),
+            }),
+            timeout: DEFAULT_TIMEOUT,
+            wait: None,
+        })
+    }
+
+    /// Connect to a Redis Cluster, `nodes` are any of its nodes to load the slot map from.
+    pub async fn cluster(nodes: &[impl AsRef<str>], password: Option<&str>) -> Result<Self> {
+        let cluster = Cluster {
+            seeds: nodes.iter().map(|s| s.as_ref().to_string()).collect(),
+            password: password.map(String::from),
+            slots: RwLock::new(vec![]),
+            pools: Mutex::new(HashMap::new()),
+        };
+        cluster.refresh(DEFAULT_TIMEOUT).await?;
         Ok(Self {
-            pool: ConnectionPool::create(address.into(), password, num_cpus::get()).await?,
+            topology: Topology::Cluster(cluster),
+            timeout: DEFAULT_TIMEOUT,
+            wait: None,
         })
     }
 }
@@ -0,0 +1,237 @@
+//! [`LeafDao`] backed by a remote leaves server instead of a database.
+//!
+//! Running a [`SegmentIDGen`](crate::SegmentIDGen) over [`RemoteLeafDao`] allocates ranges
+//! from a central server, so edge services get a hierarchical allocation tree
+//! without holding database credentials.
+//!
+//! # Protocol
+//! The server is expected to speak plain HTTP/1.1 with JSON bodies:
+//!
+//! | method   | path                                          | body   | response                                      |
+//! |----------|-----------------------------------------------|--------|-----------------------------------------------|
+//! | `GET`    | `/leaves`                                     |        | `[Leaf]`                                      |
+//! | `GET`    | `/leaves/{tag}`                               |        | `Leaf`                                        |
+//! | `POST`   | `/leaves`                                     | `Leaf` |                                               |
+//! | `PUT`    | `/leaves/{tag}`                               | `Leaf` |                                               |
+//! | `DELETE` | `/leaves/{tag}`                               |        |                                               |
+//! | `GET`    | `/tags`                                       |        | `[i32]`                                       |
+//! | `POST`   | `/leaves/{tag}/max`                           |        | `Leaf` after `max_id += step`                 |
+//! | `POST`   | `/leaves/{tag}/max?step={step}`               |        | `Leaf` after `max_id += {step}`               |
+//! | `POST`   | `/leaves/{tag}/max?step={step}&up_to={value}` |        | `Leaf` after `max_id += {step}`               |
+//! | `POST`   | `/leaves/{tag}/max?at_least={max_id}`         |        | `Leaf` after `max_id = max(max_id, {max_id})` |
+//! | `POST`   | `/leaves/{tag}/step?step={step}`              |        | `Leaf` after `step = {step}`                  |
+//!
+//! The range handed out by `/leaves/{tag}/max` with or without `step` is
+//! `max_id - step..max_id` of the returned leaf. With `up_to`, a `max_id` greater than
+//! `{value}` already is left alone and answered with `409 Conflict`.
+//! Unknown tags are answered with `404 Not Found`.
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use crate::{utils, Error, Leaf, LeafDao, Result};
+
+/// Default of [`RemoteLeafDao::with_timeout`].
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Maps [`LeafDao`] onto the protocol above, a range request is `update_max_by_step`.
+///
+/// # Examples
+/// ```no_run
+/// # async fn run() -> leaves::Result<()> {
+/// use leaves::dao::RemoteLeafDao;
+/// use leaves::segment::Config;
+/// use leaves::SegmentIDGen;
+/// use std::sync::Arc;
+///
+/// let dao = Arc::new(RemoteLeafDao::new("http://leaves.internal:8080")?);
+/// let mut service = SegmentIDGen::new(dao, Config::lazy());
+/// service.init().await?;
+/// service.get(1).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct RemoteLeafDao {
+    /// `host:port` to connect to
+    address: String,
+    host: String,
+    /// prefix of all paths, without trailing slash
+    prefix: String,
+    timeout: Duration,
+}
+
+impl RemoteLeafDao {
+    /// `url` is like `http://host:port/prefix`, https is not supported.
+    pub fn new(url: &str) -> Result<Self> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| Error::InvalidUrl(url.into()))?;
+        let (host, prefix) = match rest.find('/') {
+            Some(i) => (&rest[..i], rest[i..].trim_end_matches('/')),
+            None => (rest, ""),
+        };
+        if host.is_empty() {
+            return Err(Error::InvalidUrl(url.into()));
+        }
+        let address = if host.contains(':') {
+            host.to_string()
+        } else {
+            format!("{}:80", host)
+        };
+        Ok(Self {
+            address,
+            host: host.into(),
+            prefix: prefix.into(),
+            timeout: DEFAULT_TIMEOUT,
+        })
+    }
+
+    /// Give up a request after `timeout`, from connecting to the last byte of the response,
+    /// default is 3 seconds.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    async fn call(&self, method: &str, path: &str, body: Vec<u8>) -> Result<Vec<u8>> {
+        let mut request = format!(
+            "{} {}{} HTTP/1.1\r\n\
+             Host: {}\r\n\
+             Connection: close\r\n\
+             Content-Type: application/json\r\n\
+             Content-Length: {}\r\n\r\n",
+            method,
+            self.prefix,
+            path,
+            self.host,
+            body.len()
+        )
+        .into_bytes();
+        request.extend_from_slice(&body);
+        let response = utils::round_trip(&self.address, &request, self.timeout).await?;
+        let (status, body) = parse_response(&response)
+            .ok_or_else(|| Error::Remote("malformed http response".into()))?;
+        match status {
+            200..=299 => Ok(body),
+            404 => Err(Error::TagNotExist),
+            409 => Err(Error::TagExhausted),
+            _ => Err(Error::Remote(format!(
+                "{} {}",
+                status,
+                String::from_utf8_lossy(&body)
+            ))),
+        }
+    }
+}
+
+#[async_trait]
+impl LeafDao for RemoteLeafDao {
+    async fn leaves(&self) -> Result<Vec<Leaf>> {
+        let body = self.call("GET", "/leaves", vec![]).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    async fn leaf(&self, tag: i32) -> Result<Leaf> {
+        let body = self
+            .call("GET", &format!("/leaves/{}", tag), vec![])
+            .await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    async fn insert(&self, leaf: Leaf) -> Result<()> {
+        self.call("POST", "/leaves", serde_json::to_vec(&leaf)?)
+            .await?;
+        Ok(())
+    }
+
+    async fn tags(&self) -> Result<Vec<i32>> {
+        let body = self.call("GET", "/tags", vec![]).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    async fn update_max(&self, tag: i32) -> Result<Leaf> {
+        let body = self
+            .call("POST", &format!("/leaves/{}/max", tag), vec![])
+            .await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    async fn update_max_by_step(&self, tag: i32, step: i32) -> Result<Leaf> {
+        let path = format!("/leaves/{}/max?step={}", tag, step);
+        let body = self.call("POST", &path, vec![]).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    async fn update_max_by_step_up_to(&self, tag: i32, step: i32, max_value: i64) -> Result<Leaf> {
+        let path = format!("/leaves/{}/max?step={}&up_to={}", tag, step, max_value);
+        let body = self.call("POST", &path, vec![]).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    async fn update_step(&self, tag: i32, step: i32) -> Result<Leaf> {
+        let path = format!("/leaves/{}/step?step={}", tag, step);
+        let body = self.call("POST", &path, vec![]).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    async fn delete(&self, tag: i32) -> Result<()> {
+        self.call("DELETE", &format!("/leaves/{}", tag), vec![])
+            .await?;
+        Ok(())
+    }
+
+    async fn set_max_id_if_greater(&self, tag: i32, max_id: i64) -> Result<Leaf> {
+        let path = format!("/leaves/{}/max?at_least={}", tag, max_id);
+        let body = self.call("POST", &path, vec![]).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    async fn upsert(&self, leaf: Leaf) -> Result<()> {
+        let path = format!("/leaves/{}", leaf.tag);
+        self.call("PUT", &path, serde_json::to_vec(&leaf)?).await?;
+        Ok(())
+    }
+}
+
+/// Split a response into status code and decoded body.
+fn parse_response(raw: &[u8]) -> Option<(u16, Vec<u8>)> {
+    let head_end = raw.windows(4).position(|w| w == b"\r\n\r\n")?;
+    let head = std::str::from_utf8(&raw[..head_end]).ok()?;
+    let body = &raw[head_end + 4..];
+    let mut lines = head.split("\r\n");
+    let status = lines.next()?.split(' ').nth(1)?.parse().ok()?;
+    let mut chunked = false;
+    let mut content_length = None;
+    for line in lines {
+        let mut header = line.splitn(2, ':');
+        let (name, value) = (header.next()?.trim(), header.next()?.trim());
+        if name.eq_ignore_ascii_case("transfer-encoding") {
+            chunked = value.eq_ignore_ascii_case("chunked");
+        } else if name.eq_ignore_ascii_case("content-length") {
+            content_length = Some(value.parse::<usize>().ok()?);
+        }
+    }
+    let body = if chunked {
+        decode_chunked(body)?
+    } else if let Some(len) = content_length {
+        body.get(..len)?.to_vec()
+    } else {
+        body.to_vec()
+    };
+    Some((status, body))
+}
+
+fn decode_chunked(mut body: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = vec![];
+    loop {
+        let line_end = body.windows(2).position(|w| w == b"\r\n")?;
+        let size = std::str::from_utf8(&body[..line_end]).ok()?;
+        let size = usize::from_str_radix(size.split(';').next()?.trim(), 16).ok()?;
+        body = &body[line_end + 2..];
+        if size == 0 {
+            return Some(decoded);
+        }
+        decoded.extend_from_slice(body.get(..size)?);
+        body = body.get(size + 2..)?;
+    }
+}
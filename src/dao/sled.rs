@@ -0,0 +1,166 @@
+use std::convert::TryInto;
+use std::path::Path;
+
+use async_trait::async_trait;
+use sled::{IVec, Tree};
+
+use crate::{utils, Error, Leaf, Result};
+
+use super::{decode_leaf, encode_leaf, grow, LeafDao};
+
+/// Leaves stored in an embedded [sled](https://docs.rs/sled) tree, for single-node services.
+///
+/// Keys are big-endian tags, values are big-endian `max_id` followed by `step`.
+/// `max_id` is moved by compare-and-swap, and the tree is flushed before a range is
+/// returned, so a range handed out is never handed out again after a crash.
+///
+/// # Examples
+/// ```no_run
+/// # async fn run() -> leaves::Result<()> {
+/// use leaves::dao::SledLeafDao;
+/// use leaves::LeafDao;
+///
+/// let dao = SledLeafDao::open("/var/lib/leaves")?;
+/// let leaf = dao.update_max(1).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct SledLeafDao {
+    tree: Tree,
+}
+
+impl SledLeafDao {
+    pub fn new(tree: Tree) -> Self {
+        Self { tree }
+    }
+
+    /// Open the database at `path` and use its `leaf_alloc` tree.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let db = sled::open(path)?;
+        Ok(Self::new(db.open_tree("leaf_alloc")?))
+    }
+
+    /// `Tree::flush_async` may never complete under concurrent callers,
+    /// so the blocking flush is run off the executor instead.
+    async fn flush(&self) -> Result<()> {
+        let tree = self.tree.clone();
+        utils::spawn_blocking(move || tree.flush()).await?;
+        Ok(())
+    }
+
+    async fn update(&self, tag: i32, f: impl Fn(&mut Leaf) -> Result<()>) -> Result<Leaf> {
+        let key = tag.to_be_bytes();
+        loop {
+            let old = self.tree.get(key)?.ok_or(Error::TagNotExist)?;
+            let mut leaf = decode(&key, &old)?;
+            f(&mut leaf)?;
+            if self
+                .tree
+                .compare_and_swap(key, Some(old), Some(encode_leaf(&leaf)))?
+                .is_ok()
+            {
+                self.flush().await?;
+                return Ok(leaf);
+            }
+        }
+    }
+}
+
+fn decode(key: &[u8], value: &[u8]) -> Result<Leaf> {
+    let tag = key.try_into().map_err(|_| Error::SerializationError)?;
+    decode_leaf(i32::from_be_bytes(tag), value)
+}
+
+#[async_trait]
+impl LeafDao for SledLeafDao {
+    async fn leaves(&self) -> Result<Vec<Leaf>> {
+        self.tree
+            .iter()
+            .map(|entry| {
+                let (key, value): (IVec, IVec) = entry?;
+                decode(&key, &value)
+            })
+            .collect()
+    }
+
+    async fn leaf(&self, tag: i32) -> Result<Leaf> {
+        let key = tag.to_be_bytes();
+        let value = self.tree.get(key)?.ok_or(Error::TagNotExist)?;
+        decode(&key, &value)
+    }
+
+    async fn insert(&self, leaf: Leaf) -> Result<()> {
+        self.tree
+            .compare_and_swap(
+                leaf.tag.to_be_bytes(),
+                None as Option<&[u8]>,
+                Some(encode_leaf(&leaf)),
+            )?
+            .map_err(|_| Error::TagExists)?;
+        self.flush().await?;
+        Ok(())
+    }
+
+    async fn tags(&self) -> Result<Vec<i32>> {
+        self.tree
+            .iter()
+            .keys()
+            .map(|key| {
+                let key = key?;
+                let tag = key.as_ref().try_into();
+                Ok(i32::from_be_bytes(
+                    tag.map_err(|_| Error::SerializationError)?,
+                ))
+            })
+            .collect()
+    }
+
+    async fn update_max(&self, tag: i32) -> Result<Leaf> {
+        self.update(tag, |leaf| {
+            let step = leaf.step;
+            grow(leaf, step, None)
+        })
+        .await
+    }
+
+    async fn update_max_by_step(&self, tag: i32, step: i32) -> Result<Leaf> {
+        self.update(tag, |leaf| grow(leaf, step, None)).await
+    }
+
+    async fn update_max_by_step_up_to(&self, tag: i32, step: i32, max_value: i64) -> Result<Leaf> {
+        self.update(tag, |leaf| grow(leaf, step, Some(max_value)))
+            .await
+    }
+
+    async fn update_step(&self, tag: i32, step: i32) -> Result<Leaf> {
+        self.update(tag, |leaf| {
+            leaf.step = step;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete(&self, tag: i32) -> Result<()> {
+        self.tree
+            .remove(tag.to_be_bytes())?
+            .ok_or(Error::TagNotExist)?;
+        self.flush().await?;
+        Ok(())
+    }
+
+    async fn set_max_id_if_greater(&self, tag: i32, max_id: i64) -> Result<Leaf> {
+        self.update(tag, |leaf| {
+            leaf.max_id = leaf.max_id.max(max_id);
+            Ok(())
+        })
+        .await
+    }
+
+    async fn upsert(&self, leaf: Leaf) -> Result<()> {
+        self.tree
+            .insert(leaf.tag.to_be_bytes(), encode_leaf(&leaf))?;
+        self.flush().await?;
+        Ok(())
+    }
+}
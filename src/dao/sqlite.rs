@@ -1,9 +1,8 @@
-use sqlx::row::Row;
 use sqlx::sqlite::{SqlitePool, SqliteQueryAs};
 
 use async_trait::async_trait;
 
-use crate::{Leaf, LeafDao, Result};
+use crate::{Error, FreeRange, Leaf, LeafDao, Result};
 
 pub struct SqliteLeafDao {
     pool: SqlitePool,
@@ -46,29 +45,152 @@ impl LeafDao for SqliteLeafDao {
     }
 
     async fn update_max(&self, tag: i32) -> Result<Leaf> {
-        let mut conn = self.pool.acquire().await?;
+        // read in the same transaction, or a concurrent increment could be read instead
+        let mut tx = self.pool.begin().await?;
         sqlx::query("UPDATE leaf_alloc SET max_id = max_id + step WHERE tag = ?")
             .bind(tag)
-            .execute(&mut conn)
+            .execute(&mut tx)
+            .await?;
+        let leaf: Leaf = sqlx::query_as("SELECT tag, max_id, step FROM leaf_alloc WHERE tag = ?")
+            .bind(tag)
+            .fetch_one(&mut tx)
             .await?;
-        self.get_leaf(tag).await
+        tx.commit().await?;
+        Ok(leaf)
     }
 
     async fn update_max_by_step(&self, tag: i32, step: i32) -> Result<Leaf> {
-        let mut conn = self.pool.acquire().await?;
+        let mut tx = self.pool.begin().await?;
         sqlx::query("UPDATE leaf_alloc SET max_id = max_id + ? WHERE tag = ?")
             .bind(step)
+            .bind(tag)
+            .execute(&mut tx)
+            .await?;
+        let leaf: Leaf = sqlx::query_as("SELECT tag, max_id, step FROM leaf_alloc WHERE tag = ?")
+            .bind(tag)
+            .fetch_one(&mut tx)
+            .await?;
+        tx.commit().await?;
+        Ok(leaf)
+    }
+
+    async fn update_max_by_step_up_to(&self, tag: i32, step: i32, max_value: i64) -> Result<Leaf> {
+        let mut tx = self.pool.begin().await?;
+        let rows =
+            sqlx::query("UPDATE leaf_alloc SET max_id = max_id + ? WHERE tag = ? AND max_id <= ?")
+                .bind(step)
+                .bind(tag)
+                .bind(max_value)
+                .execute(&mut tx)
+                .await?;
+        let leaf: Leaf = sqlx::query_as("SELECT tag, max_id, step FROM leaf_alloc WHERE tag = ?")
+            .bind(tag)
+            .fetch_one(&mut tx)
+            .await?;
+        tx.commit().await?;
+        if rows == 0 {
+            return Err(Error::TagExhausted);
+        }
+        Ok(leaf)
+    }
+
+    async fn update_step(&self, tag: i32, step: i32) -> Result<Leaf> {
+        let mut conn = self.pool.acquire().await?;
+        sqlx::query("UPDATE leaf_alloc SET step = ? WHERE tag = ?")
+            .bind(step)
+            .bind(tag)
+            .execute(&mut conn)
+            .await?;
+        let leaf: Leaf = sqlx::query_as("SELECT tag, max_id, step FROM leaf_alloc WHERE tag = ?")
+            .bind(tag)
+            .fetch_one(&mut conn)
+            .await?;
+        Ok(leaf)
+    }
+
+    async fn delete(&self, tag: i32) -> Result<()> {
+        let mut conn = self.pool.acquire().await?;
+        let rows = sqlx::query("DELETE FROM leaf_alloc WHERE tag = ?")
             .bind(tag)
             .execute(&mut conn)
             .await?;
-        self.get_leaf(tag).await
+        if rows == 0 {
+            return Err(Error::TagNotExist);
+        }
+        Ok(())
+    }
+
+    async fn set_max_id_if_greater(&self, tag: i32, max_id: i64) -> Result<Leaf> {
+        let mut conn = self.pool.acquire().await?;
+        sqlx::query("UPDATE leaf_alloc SET max_id = ? WHERE tag = ? AND max_id < ?")
+            .bind(max_id)
+            .bind(tag)
+            .bind(max_id)
+            .execute(&mut conn)
+            .await?;
+        let leaf: Leaf = sqlx::query_as("SELECT tag, max_id, step FROM leaf_alloc WHERE tag = ?")
+            .bind(tag)
+            .fetch_one(&mut conn)
+            .await?;
+        Ok(leaf)
+    }
+
+    async fn upsert(&self, leaf: Leaf) -> Result<()> {
+        let mut conn = self.pool.acquire().await?;
+        sqlx::query("INSERT OR REPLACE INTO leaf_alloc (tag, max_id, step) VALUES (?, ?, ?)")
+            .bind(leaf.tag)
+            .bind(leaf.max_id)
+            .bind(leaf.step)
+            .execute(&mut conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn push_free_range(&self, range: FreeRange) -> Result<()> {
+        let mut conn = self.pool.acquire().await?;
+        sqlx::query("INSERT INTO leaf_free_ranges (tag, start_id, end_id) VALUES (?, ?, ?)")
+            .bind(range.tag)
+            .bind(range.start)
+            .bind(range.end)
+            .execute(&mut conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn pop_free_range(&self, tag: i32) -> Result<Option<FreeRange>> {
+        let mut conn = self.pool.acquire().await?;
+        loop {
+            let row: Option<(i64, i64)> = sqlx::query_as(
+                "SELECT start_id, end_id FROM leaf_free_ranges WHERE tag = ? ORDER BY start_id LIMIT 1",
+            )
+            .bind(tag)
+            .fetch_optional(&mut conn)
+            .await?;
+            let (start, end) = match row {
+                Some(row) => row,
+                None => return Ok(None),
+            };
+            let rows = sqlx::query("DELETE FROM leaf_free_ranges WHERE tag = ? AND start_id = ?")
+                .bind(tag)
+                .bind(start)
+                .execute(&mut conn)
+                .await?;
+            // taken by another instance in between otherwise
+            if rows > 0 {
+                return Ok(Some(FreeRange { tag, start, end }));
+            }
+        }
     }
 }
 
 impl SqliteLeafDao {
+    /// Statements run on a single connection one after another: sqlx opens SQLite in
+    /// shared-cache mode, where a statement conflicting with a concurrent transaction fails
+    /// with `database table is locked` at once instead of waiting, and SQLite has a single
+    /// writer anyway.
     pub async fn new(db_url: &str) -> Result<Self> {
         Ok(Self {
-            pool: SqlitePool::new(db_url).await?,
+            pool: SqlitePool::builder().max_size(1).build(db_url).await?,
         })
     }
 
@@ -83,6 +205,16 @@ impl SqliteLeafDao {
         )
         .execute(&mut conn)
         .await?;
+        sqlx::query(
+            r#"CREATE TABLE leaf_free_ranges (
+                    tag INT NOT NULL,
+                    start_id BIGINT NOT NULL,
+                    end_id BIGINT NOT NULL,
+                    PRIMARY KEY (tag, start_id)
+                )"#,
+        )
+        .execute(&mut conn)
+        .await?;
         Ok(())
     }
 }
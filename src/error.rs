@@ -4,18 +4,35 @@ pub type Result<T> = std::result::Result<T, Error>;
 pub enum Error {
     #[error("tag not exist")]
     TagNotExist,
+    #[error("tag exists")]
+    TagExists,
+    #[error("tag exhausted")]
+    TagExhausted,
     #[error("both segment not ready")]
     BothSegmentsNotReady,
     #[error("service not ready")]
     ServiceNotReady,
     #[error("serialization error")]
     SerializationError,
+    #[error("invalid url: {0}")]
+    InvalidUrl(String),
+    #[error("invalid config: {0}")]
+    InvalidConfig(String),
+    #[error("invalid id: {0}")]
+    InvalidId(String),
+    #[error("fault injected")]
+    FaultInjected,
+    #[error("not supported: {0}")]
+    Unsupported(String),
     #[cfg(any(feature = "mysql", feature = "postgres", feature = "sqlite"))]
     #[error("sqlx error")]
     SqlX(#[from] sqlx::error::Error),
     #[cfg(feature = "redis")]
     #[error("redis error")]
     Redis(#[from] darkredis::Error),
+    #[cfg(feature = "redis")]
+    #[error("only {0} of {1} replicas acknowledged")]
+    NotReplicated(usize, usize),
     #[cfg(feature = "mongo")]
     #[error("mongodb error")]
     MongoDB(#[from] mongodb::error::Error),
@@ -25,4 +42,19 @@ pub enum Error {
     #[cfg(feature = "mongo")]
     #[error("bson decoder error")]
     BsonDecode(#[from] bson::de::Error),
+    #[cfg(feature = "sled")]
+    #[error("sled error")]
+    Sled(#[from] sled::Error),
+    #[cfg(any(feature = "client", feature = "migrate", feature = "file"))]
+    #[error("io error")]
+    Io(#[from] std::io::Error),
+    #[cfg(any(feature = "client", feature = "migrate", feature = "file"))]
+    #[error("json error")]
+    Json(#[from] serde_json::Error),
+    #[cfg(any(feature = "client", feature = "redis"))]
+    #[error("remote error: {0}")]
+    Remote(String),
+    #[cfg(feature = "migrate")]
+    #[error("invalid dump: {0}")]
+    InvalidDump(String),
 }
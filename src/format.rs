@@ -0,0 +1,355 @@
+//! Render IDs as strings by templates, and parse them back.
+//!
+//! A template is literal text with fields in braces, `{{` and `}}` are literal braces:
+//!
+//! | field                     | renders                                                   |
+//! |---------------------------|-----------------------------------------------------------|
+//! | `{seq}`, `{seq:08}`       | the ID, zero padded to 8 digits in the latter             |
+//! | `{yyyyMMdd}`, `{yy-MM}`.. | date parts `yyyy`, `yy`, `MM`, `dd`, `HH`, `mm` and `ss`  |
+//! | `{luhn}`                  | Luhn check digit of all digits before it                  |
+//! | `{damm}`                  | Damm check digit of all digits before it                  |
+//!
+//! Dates are the time of formatting, in UTC unless another time zone is set.
+//!
+//! # Examples
+//! ```
+//! use std::time::{Duration, UNIX_EPOCH};
+//! use leaves::format::IdFormatter;
+//!
+//! let formatter = IdFormatter::new("ORD{yyyyMMdd}{seq:08}{luhn}").unwrap();
+//! // 2026-10-17 12:00:00 UTC
+//! let now = UNIX_EPOCH + Duration::from_secs(1_792_238_400);
+//! let id = formatter.format_at(123, now).unwrap();
+//! assert_eq!(id, "ORD20261017000001236");
+//! assert_eq!(formatter.parse(&id).unwrap(), 123);
+//! assert!(formatter.parse("ORD20261017000001239").is_err());
+//! ```
+use std::fmt::Write;
+use std::time::SystemTime;
+
+use chrono::{DateTime, Datelike, Timelike, Utc};
+
+use crate::codec::Codec;
+use crate::{Error, Result};
+
+pub use chrono_tz::Tz;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum DateField {
+    Year,
+    ShortYear,
+    Month,
+    Day,
+    Hour,
+    Minute,
+    Second,
+}
+
+/// Date fields by pattern, longer ones first.
+const DATE_FIELDS: [(&str, DateField); 7] = [
+    ("yyyy", DateField::Year),
+    ("yy", DateField::ShortYear),
+    ("MM", DateField::Month),
+    ("dd", DateField::Day),
+    ("HH", DateField::Hour),
+    ("mm", DateField::Minute),
+    ("ss", DateField::Second),
+];
+
+impl DateField {
+    fn width(self) -> usize {
+        match self {
+            DateField::Year => 4,
+            _ => 2,
+        }
+    }
+
+    fn value(self, date: &DateTime<Tz>) -> i64 {
+        match self {
+            DateField::Year => date.year() as i64,
+            DateField::ShortYear => date.year() as i64 % 100,
+            DateField::Month => date.month() as i64,
+            DateField::Day => date.day() as i64,
+            DateField::Hour => date.hour() as i64,
+            DateField::Minute => date.minute() as i64,
+            DateField::Second => date.second() as i64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Date(DateField),
+    /// zero padded to `width` digits
+    Seq(usize),
+    Luhn,
+    Damm,
+}
+
+impl Part {
+    /// digits rendered, `None` if it's not a fixed number of digits
+    fn width(&self) -> Option<usize> {
+        match self {
+            Part::Date(field) => Some(field.width()),
+            Part::Luhn | Part::Damm => Some(1),
+            Part::Literal(_) | Part::Seq(_) => None,
+        }
+    }
+}
+
+/// Renders IDs by a template, see the [module](self) for its syntax.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdFormatter {
+    parts: Vec<Part>,
+    time_zone: Tz,
+}
+
+impl IdFormatter {
+    /// Compile `template`, it needs exactly one `{seq}`.
+    pub fn new(template: &str) -> Result<Self> {
+        let mut parts = vec![];
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    push_literal(&mut parts, '{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    push_literal(&mut parts, '}');
+                }
+                '{' => {
+                    let mut field = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => field.push(c),
+                            None => return Err(invalid_template(template, "unclosed `{`")),
+                        }
+                    }
+                    parse_field(template, &field, &mut parts)?;
+                }
+                '}' => return Err(invalid_template(template, "unmatched `}`")),
+                c => push_literal(&mut parts, c),
+            }
+        }
+
+        let seqs = parts.iter().filter(|p| matches!(p, Part::Seq(_))).count();
+        if seqs != 1 {
+            return Err(invalid_template(template, "exactly one `{seq}` is needed"));
+        }
+        let seq = parts
+            .iter()
+            .position(|p| matches!(p, Part::Seq(_)))
+            .unwrap();
+        if let Some(Part::Literal(literal)) =
+            parts[seq + 1..].iter().find(|part| part.width().is_none())
+        {
+            // or there's no telling where the sequence ends
+            if literal.starts_with(|c: char| c.is_ascii_digit()) {
+                return Err(invalid_template(
+                    template,
+                    "text after `{seq}` can't start with a digit",
+                ));
+            }
+        }
+        Ok(Self {
+            parts,
+            time_zone: Tz::UTC,
+        })
+    }
+
+    /// Time zone of date fields, UTC by default.
+    pub fn set_time_zone(mut self, time_zone: Tz) -> Self {
+        self.time_zone = time_zone;
+        self
+    }
+
+    /// Render `id` dated now.
+    #[inline]
+    pub fn format(&self, id: i64) -> Result<String> {
+        self.format_at(id, SystemTime::now())
+    }
+
+    /// Render `id` dated `now`, it can't be negative.
+    pub fn format_at(&self, id: i64, now: SystemTime) -> Result<String> {
+        if id < 0 {
+            return Err(Error::InvalidId(format!("{} is negative", id)));
+        }
+        let now = DateTime::<Utc>::from(now).with_timezone(&self.time_zone);
+        let mut rendered = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(literal) => rendered.push_str(literal),
+                Part::Date(field) => {
+                    let _ = write!(
+                        rendered,
+                        "{:0width$}",
+                        field.value(&now),
+                        width = field.width()
+                    );
+                }
+                Part::Seq(width) => {
+                    let _ = write!(rendered, "{:0width$}", id, width = width);
+                }
+                Part::Luhn => rendered.push(luhn(&rendered)),
+                Part::Damm => rendered.push(damm(&rendered)),
+            }
+        }
+        Ok(rendered)
+    }
+
+    /// Validate `s` against the template, check digits included, and extract the ID.
+    /// Date fields are only checked to be digits.
+    pub fn parse(&self, s: &str) -> Result<i64> {
+        let mismatch = |reason: &str| Error::InvalidId(format!("`{}`: {}", s, reason));
+        let mut pos = 0;
+        let mut seq = 0;
+        for (i, part) in self.parts.iter().enumerate() {
+            let rest = &s[pos..];
+            let run = rest.bytes().take_while(u8::is_ascii_digit).count();
+            match part {
+                Part::Literal(literal) => {
+                    if !rest.starts_with(literal.as_str()) {
+                        return Err(mismatch(&format!("`{}` expected at {}", literal, pos)));
+                    }
+                    pos += literal.len();
+                }
+                Part::Date(field) => {
+                    if run < field.width() {
+                        return Err(mismatch(&format!("date expected at {}", pos)));
+                    }
+                    pos += field.width();
+                }
+                Part::Seq(width) => {
+                    // the digits following it up to the next text have fixed widths
+                    let fixed: usize = self.parts[i + 1..].iter().map_while(Part::width).sum();
+                    let len = run.saturating_sub(fixed);
+                    if len < (*width).max(1) {
+                        return Err(mismatch(&format!("sequence expected at {}", pos)));
+                    }
+                    seq = rest[..len]
+                        .parse()
+                        .map_err(|_| mismatch("sequence out of range"))?;
+                    pos += len;
+                }
+                Part::Luhn | Part::Damm => {
+                    let expected = if *part == Part::Luhn {
+                        luhn(&s[..pos])
+                    } else {
+                        damm(&s[..pos])
+                    };
+                    if !rest.starts_with(expected) {
+                        return Err(mismatch(&format!("wrong check digit at {}", pos)));
+                    }
+                    pos += 1;
+                }
+            }
+        }
+        if pos != s.len() {
+            return Err(mismatch(&format!("unexpected `{}`", &s[pos..])));
+        }
+        Ok(seq)
+    }
+}
+
+impl Codec for IdFormatter {
+    fn encode(&self, id: i64) -> Result<String> {
+        self.format(id)
+    }
+
+    fn decode(&self, s: &str) -> Result<i64> {
+        self.parse(s)
+    }
+}
+
+fn invalid_template(template: &str, reason: &str) -> Error {
+    Error::InvalidConfig(format!("template `{}`: {}", template, reason))
+}
+
+fn push_literal(parts: &mut Vec<Part>, c: char) {
+    match parts.last_mut() {
+        Some(Part::Literal(literal)) => literal.push(c),
+        _ => parts.push(Part::Literal(c.to_string())),
+    }
+}
+
+fn parse_field(template: &str, field: &str, parts: &mut Vec<Part>) -> Result<()> {
+    match field {
+        "seq" => parts.push(Part::Seq(0)),
+        "luhn" => parts.push(Part::Luhn),
+        "damm" => parts.push(Part::Damm),
+        _ if field.starts_with("seq:") => {
+            let width = field["seq:".len()..]
+                .strip_prefix('0')
+                .and_then(|width| width.parse().ok())
+                .filter(|width| (1..=19).contains(width))
+                .ok_or_else(|| {
+                    invalid_template(template, "padding of `{seq:0N}` is 1 to 19 digits")
+                })?;
+            parts.push(Part::Seq(width));
+        }
+        _ => {
+            let mut rest = field;
+            while let Some(c) = rest.chars().next() {
+                if let Some((pattern, field)) = DATE_FIELDS
+                    .iter()
+                    .find(|(pattern, _)| rest.starts_with(pattern))
+                {
+                    parts.push(Part::Date(*field));
+                    rest = &rest[pattern.len()..];
+                } else if c.is_ascii_alphabetic() {
+                    return Err(invalid_template(
+                        template,
+                        &format!("unknown field `{{{}}}`", field),
+                    ));
+                } else {
+                    push_literal(parts, c);
+                    rest = &rest[c.len_utf8()..];
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn digits(s: &str) -> impl DoubleEndedIterator<Item = u32> + '_ {
+    s.chars().filter_map(|c| c.to_digit(10))
+}
+
+/// Luhn check digit of the digits in `s`.
+fn luhn(s: &str) -> char {
+    let sum: u32 = digits(s)
+        .rev()
+        .enumerate()
+        .map(|(i, digit)| match (i % 2, digit * 2) {
+            (0, doubled) if doubled > 9 => doubled - 9,
+            (0, doubled) => doubled,
+            _ => digit,
+        })
+        .sum();
+    std::char::from_digit((10 - sum % 10) % 10, 10).unwrap()
+}
+
+const DAMM: [[u8; 10]; 10] = [
+    [0, 3, 1, 7, 5, 9, 8, 6, 4, 2],
+    [7, 0, 9, 2, 1, 5, 4, 8, 6, 3],
+    [4, 2, 0, 6, 8, 7, 1, 3, 5, 9],
+    [1, 7, 5, 0, 9, 8, 3, 4, 2, 6],
+    [6, 1, 2, 3, 0, 4, 5, 9, 7, 8],
+    [3, 6, 7, 4, 2, 0, 9, 5, 8, 1],
+    [5, 8, 6, 9, 7, 2, 0, 1, 3, 4],
+    [8, 9, 4, 5, 3, 6, 2, 0, 1, 7],
+    [9, 4, 3, 8, 6, 1, 7, 2, 0, 5],
+    [2, 5, 8, 1, 4, 3, 6, 7, 9, 0],
+];
+
+/// Damm check digit of the digits in `s`.
+fn damm(s: &str) -> char {
+    let interim = digits(s).fold(0, |interim, digit| {
+        DAMM[interim as usize][digit as usize] as u32
+    });
+    std::char::from_digit(interim, 10).unwrap()
+}
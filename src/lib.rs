@@ -8,12 +8,26 @@ compile_error!("one of 'runtime-async-std' or 'runtime-tokio' features must be e
 #[cfg(all(feature = "runtime-tokio", feature = "runtime-async-std"))]
 compile_error!("only one of 'runtime-async-std' or 'runtime-tokio' features must be enabled");
 
+#[cfg(feature = "client")]
+pub mod client;
+pub mod clock;
+pub mod codec;
+#[cfg(feature = "config")]
+pub mod config;
 pub mod dao;
 pub mod error;
+#[cfg(feature = "format")]
+pub mod format;
+pub mod metrics;
+#[cfg(feature = "migrate")]
+pub mod migrate;
+pub mod obfuscate;
+#[cfg(feature = "periodic")]
+pub mod periodic;
 pub mod segment;
 mod utils;
 
-#[derive(Debug, Copy, Clone, serde::Deserialize, serde::Serialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
 #[cfg_attr(
     any(feature = "mysql", feature = "postgres", feature = "sqlite"),
     derive(sqlx::FromRow)
@@ -26,3 +40,11 @@ pub struct Leaf {
     /// step when updating `max_id`
     pub step: i32,
 }
+
+/// IDs `[start, end)` of a tag allocated but never handed out.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct FreeRange {
+    pub tag: i32,
+    pub start: i64,
+    pub end: i64,
+}
@@ -0,0 +1,179 @@
+//! Prometheus metrics of [`SegmentIDGen`](crate::SegmentIDGen).
+//!
+//! Everything is recorded into a dedicated `prometheus::Registry`, which can be exported with
+//! `render` or merged into an existing exporter with `registry`.
+//! Without the `metrics` feature all recording functions are no-ops.
+use std::future::Future;
+
+use crate::Result;
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "metrics")] {
+        use std::time::Instant;
+
+        use dashmap::DashMap;
+        use lazy_static::lazy_static;
+        use prometheus::{
+            Encoder, GaugeVec, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts,
+            Registry, TextEncoder,
+        };
+
+        use crate::Error;
+
+        lazy_static! {
+            static ref REGISTRY: Registry = Registry::new_custom(Some("leaves".into()), None)
+                .expect("valid registry prefix");
+            static ref IDS_ISSUED: IntCounterVec = register(IntCounterVec::new(
+                Opts::new("ids_issued_total", "IDs handed out by SegmentIDGen"),
+                &["tag"]
+            ));
+            static ref SEGMENT_SWITCHES: IntCounterVec = register(IntCounterVec::new(
+                Opts::new("segment_switches_total", "switches from current to next segment"),
+                &["tag"]
+            ));
+            static ref BOTH_SEGMENTS_NOT_READY: IntCounterVec = register(IntCounterVec::new(
+                Opts::new(
+                    "both_segments_not_ready_total",
+                    "requests failed because both segments were exhausted"
+                ),
+                &["tag"]
+            ));
+            static ref PRELOAD_FAILURES: IntCounterVec = register(IntCounterVec::new(
+                Opts::new("preload_failures_total", "failed background loads of the next segment"),
+                &["tag"]
+            ));
+            static ref STEP: IntGaugeVec = register(IntGaugeVec::new(
+                Opts::new("step", "current step of a tag"),
+                &["tag"]
+            ));
+            static ref MAX_VALUE_USAGE: GaugeVec = register(GaugeVec::new(
+                Opts::new("max_value_usage_ratio", "share of IDs up to max_value allocated"),
+                &["tag"]
+            ));
+            static ref DAO_DURATION: HistogramVec = register(HistogramVec::new(
+                HistogramOpts::new("dao_duration_seconds", "latency of LeafDao calls"),
+                &["op"]
+            ));
+            /// label values of tags, so recording doesn't allocate every time
+            static ref TAG_LABELS: DashMap<i32, String> = DashMap::new();
+        }
+
+        /// Record into the metric labeled by `tag`.
+        fn with_tag<R>(tag: i32, record: impl FnOnce(&[&str]) -> R) -> R {
+            if let Some(label) = TAG_LABELS.get(&tag) {
+                return record(&[label.as_str()]);
+            }
+            let label = TAG_LABELS.entry(tag).or_insert_with(|| tag.to_string());
+            record(&[label.as_str()])
+        }
+
+        fn register<T: prometheus::core::Collector + Clone + 'static>(
+            collector: prometheus::Result<T>,
+        ) -> T {
+            let collector = collector.expect("valid metric");
+            REGISTRY
+                .register(Box::new(collector.clone()))
+                .expect("metric registered once");
+            collector
+        }
+
+        /// The registry all metrics of this crate are recorded into.
+        pub fn registry() -> &'static Registry {
+            &REGISTRY
+        }
+
+        /// Render all metrics in the Prometheus text exposition format.
+        ///
+        /// # Examples
+        /// ```no_run
+        /// let body = leaves::metrics::render();
+        /// // serve `body` with content type `leaves::metrics::TEXT_FORMAT` on `/metrics`
+        /// ```
+        pub fn render() -> String {
+            let mut buf = vec![];
+            TextEncoder::new()
+                .encode(&REGISTRY.gather(), &mut buf)
+                .expect("text encoding never fails");
+            String::from_utf8(buf).expect("text encoding is utf8")
+        }
+
+        /// Content type of [`render`]'s output.
+        pub const TEXT_FORMAT: &str = "text/plain; version=0.0.4";
+    }
+}
+
+#[inline]
+pub(crate) fn record_get(tag: i32, result: &Result<i64>) {
+    #[cfg(feature = "metrics")]
+    match result {
+        Ok(_) => with_tag(tag, |labels| IDS_ISSUED.with_label_values(labels).inc()),
+        Err(Error::BothSegmentsNotReady) => with_tag(tag, |labels| {
+            BOTH_SEGMENTS_NOT_READY.with_label_values(labels).inc()
+        }),
+        Err(_) => {}
+    }
+    #[cfg(not(feature = "metrics"))]
+    let _ = (tag, result);
+}
+
+#[inline]
+pub(crate) fn record_switch(tag: i32) {
+    #[cfg(feature = "metrics")]
+    with_tag(tag, |labels| {
+        SEGMENT_SWITCHES.with_label_values(labels).inc()
+    });
+    #[cfg(not(feature = "metrics"))]
+    let _ = tag;
+}
+
+#[inline]
+pub(crate) fn record_preload_failure(tag: i32) {
+    #[cfg(feature = "metrics")]
+    with_tag(tag, |labels| {
+        PRELOAD_FAILURES.with_label_values(labels).inc()
+    });
+    #[cfg(not(feature = "metrics"))]
+    let _ = tag;
+}
+
+#[inline]
+pub(crate) fn record_step(tag: i32, step: i32) {
+    #[cfg(feature = "metrics")]
+    with_tag(tag, |labels| {
+        STEP.with_label_values(labels).set(step as i64)
+    });
+    #[cfg(not(feature = "metrics"))]
+    let _ = (tag, step);
+}
+
+#[inline]
+pub(crate) fn record_usage(tag: i32, usage: f64) {
+    #[cfg(feature = "metrics")]
+    with_tag(tag, |labels| {
+        MAX_VALUE_USAGE.with_label_values(labels).set(usage)
+    });
+    #[cfg(not(feature = "metrics"))]
+    let _ = (tag, usage);
+}
+
+/// Time a `LeafDao` call labeled by `op`.
+#[inline]
+pub(crate) async fn time_dao<T>(
+    op: &'static str,
+    fut: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    #[cfg(feature = "metrics")]
+    {
+        let start = Instant::now();
+        let result = fut.await;
+        DAO_DURATION
+            .with_label_values(&[op])
+            .observe(start.elapsed().as_secs_f64());
+        result
+    }
+    #[cfg(not(feature = "metrics"))]
+    {
+        let _ = op;
+        fut.await
+    }
+}
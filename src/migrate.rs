@@ -0,0 +1,248 @@
+//! Moving leaves between backends, and dumping them for backups.
+//!
+//! IDs allocated from the source after it was read would be reused by the target,
+//! so every `max_id` written is bumped by a safety margin, which should be larger than
+//! the number of IDs the source could still hand out, e.g. a few `step`s of every tag.
+//! `max_id` in the target never moves backwards.
+//!
+//! # Examples
+//! ```no_run
+//! # async fn run() -> leaves::Result<()> {
+//! use leaves::migrate;
+//!
+//! let source = leaves::dao::connect("mongodb://localhost/leaves").await?;
+//! let target = leaves::dao::connect("postgres://localhost/leaves").await?;
+//! for change in migrate::migrate(&source, &target, 100_000, true).await? {
+//!     println!("{}", change);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::str::FromStr;
+
+use crate::{Error, Leaf, LeafDao, Result};
+
+/// A change to be made to the target.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// the tag does not exist in the target
+    Insert(Leaf),
+    /// the tag exists in the target, `max_id` or `step` changes
+    Update { from: Leaf, to: Leaf },
+    /// `max_id` of the target is already ahead
+    Unchanged(Leaf),
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Insert(leaf) => write!(
+                f,
+                "+ tag {}: max_id {}, step {}",
+                leaf.tag, leaf.max_id, leaf.step
+            ),
+            Change::Update { from, to } => write!(
+                f,
+                "~ tag {}: max_id {} -> {}, step {} -> {}",
+                to.tag, from.max_id, to.max_id, from.step, to.step
+            ),
+            Change::Unchanged(leaf) => write!(
+                f,
+                "= tag {}: max_id {}, step {}",
+                leaf.tag, leaf.max_id, leaf.step
+            ),
+        }
+    }
+}
+
+/// Compare `leaves` bumped by `margin` with those in `target`, nothing is written.
+/// `margin` can't be negative, or IDs handed out by the source would be again.
+pub async fn plan<D>(leaves: Vec<Leaf>, target: &D, margin: i64) -> Result<Vec<Change>>
+where
+    D: LeafDao + ?Sized,
+{
+    if margin < 0 {
+        return Err(Error::InvalidConfig(format!(
+            "margin can't be negative, got {}",
+            margin
+        )));
+    }
+    let existing = target
+        .leaves()
+        .await?
+        .into_iter()
+        .map(|leaf| (leaf.tag, leaf))
+        .collect::<HashMap<_, _>>();
+    let mut changes = leaves
+        .into_iter()
+        .map(|leaf| {
+            let mut to = leaf;
+            to.max_id = leaf.max_id.saturating_add(margin);
+            match existing.get(&leaf.tag) {
+                None => Change::Insert(to),
+                Some(&from) => {
+                    to.max_id = to.max_id.max(from.max_id);
+                    if to.max_id == from.max_id && to.step == from.step {
+                        Change::Unchanged(from)
+                    } else {
+                        Change::Update { from, to }
+                    }
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+    changes.sort_by_key(|change| match change {
+        Change::Insert(leaf) | Change::Unchanged(leaf) | Change::Update { to: leaf, .. } => {
+            leaf.tag
+        }
+    });
+    Ok(changes)
+}
+
+/// Write `changes` made by [`plan`] into `target`.
+pub async fn apply<D>(changes: &[Change], target: &D) -> Result<()>
+where
+    D: LeafDao + ?Sized,
+{
+    for change in changes {
+        match *change {
+            Change::Insert(leaf) => target.insert(leaf).await?,
+            Change::Update { from, to } => {
+                if to.max_id != from.max_id {
+                    target.set_max_id_if_greater(to.tag, to.max_id).await?;
+                }
+                if to.step != from.step {
+                    target.update_step(to.tag, to.step).await?;
+                }
+            }
+            Change::Unchanged(_) => {}
+        }
+    }
+    Ok(())
+}
+
+/// Copy all leaves from `source` to `target` with `max_id` bumped by `margin`.
+/// Changes are only reported if `dry_run` is set.
+pub async fn migrate<S, T>(
+    source: &S,
+    target: &T,
+    margin: i64,
+    dry_run: bool,
+) -> Result<Vec<Change>>
+where
+    S: LeafDao + ?Sized,
+    T: LeafDao + ?Sized,
+{
+    let changes = plan(source.leaves().await?, target, margin).await?;
+    if !dry_run {
+        apply(&changes, target).await?;
+    }
+    Ok(changes)
+}
+
+/// Format of dumps.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DumpFormat {
+    /// an array of leaves
+    Json,
+    /// `tag,max_id,step` with a header line
+    Csv,
+}
+
+impl FromStr for DumpFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            _ => Err(Error::InvalidDump(format!(
+                "unknown format `{}`, expected json or csv",
+                s
+            ))),
+        }
+    }
+}
+
+const CSV_HEADER: &str = "tag,max_id,step";
+
+/// Write all leaves of `dao` to `writer`, sorted by tag.
+pub async fn export<D>(dao: &D, writer: impl Write, format: DumpFormat) -> Result<()>
+where
+    D: LeafDao + ?Sized,
+{
+    let mut leaves = dao.leaves().await?;
+    leaves.sort_by_key(|leaf| leaf.tag);
+    write_dump(writer, &leaves, format)
+}
+
+/// Read a dump and write it into `target` like [`migrate`] does.
+pub async fn import<D>(
+    target: &D,
+    reader: impl Read,
+    format: DumpFormat,
+    margin: i64,
+    dry_run: bool,
+) -> Result<Vec<Change>>
+where
+    D: LeafDao + ?Sized,
+{
+    let changes = plan(read_dump(reader, format)?, target, margin).await?;
+    if !dry_run {
+        apply(&changes, target).await?;
+    }
+    Ok(changes)
+}
+
+pub fn write_dump(mut writer: impl Write, leaves: &[Leaf], format: DumpFormat) -> Result<()> {
+    match format {
+        DumpFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, leaves)?;
+            writeln!(writer)?;
+        }
+        DumpFormat::Csv => {
+            writeln!(writer, "{}", CSV_HEADER)?;
+            for leaf in leaves {
+                writeln!(writer, "{},{},{}", leaf.tag, leaf.max_id, leaf.step)?;
+            }
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn read_dump(reader: impl Read, format: DumpFormat) -> Result<Vec<Leaf>> {
+    match format {
+        DumpFormat::Json => Ok(serde_json::from_reader(reader)?),
+        DumpFormat::Csv => {
+            let mut leaves = vec![];
+            for (i, line) in BufReader::new(reader).lines().enumerate() {
+                let line = line?;
+                let line = line.trim();
+                if line.is_empty() || (i == 0 && line == CSV_HEADER) {
+                    continue;
+                }
+                leaves.push(parse_csv_line(line).ok_or_else(|| {
+                    Error::InvalidDump(format!("line {}: expected {}", i + 1, CSV_HEADER))
+                })?);
+            }
+            Ok(leaves)
+        }
+    }
+}
+
+fn parse_csv_line(line: &str) -> Option<Leaf> {
+    let mut fields = line.split(',').map(str::trim);
+    let leaf = Leaf {
+        tag: fields.next()?.parse().ok()?,
+        max_id: fields.next()?.parse().ok()?,
+        step: fields.next()?.parse().ok()?,
+    };
+    match fields.next() {
+        Some(_) => None,
+        None => Some(leaf),
+    }
+}
@@ -0,0 +1,133 @@
+//! Reversible scrambling of IDs, so they don't tell how many were handed out.
+//!
+//! [`Feistel`] permutes `[0, 2^bits)` by a Feistel network keyed by a secret: every ID maps
+//! to a unique one of the same range and back. It keeps IDs from being guessed or counted,
+//! it's no encryption though, keep the secret anyway.
+//!
+//! # Examples
+//! ```
+//! use leaves::obfuscate::Feistel;
+//!
+//! let feistel = Feistel::new(40, 0x5eed);
+//! let scrambled = feistel.encode(1).unwrap();
+//! assert!(scrambled < 1 << 40);
+//! assert_eq!(feistel.decode(scrambled).unwrap(), 1);
+//! ```
+use crate::{Error, Result};
+
+const ROUNDS: usize = 8;
+
+/// A keyed permutation of `[0, 2^bits)`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Feistel {
+    bits: u32,
+    /// width of each half, the network permutes `2 * half` bits
+    half: u32,
+    keys: [u64; ROUNDS],
+}
+
+impl Feistel {
+    /// Permute IDs of `bits` bits by `secret`.
+    ///
+    /// # Panics
+    /// If `bits` isn't in `1..=63`.
+    pub fn new(bits: u32, secret: u64) -> Self {
+        assert!(
+            (1..=63).contains(&bits),
+            "bits must be in 1..=63, got {}",
+            bits
+        );
+        let mut state = secret;
+        let mut keys = [0; ROUNDS];
+        for key in keys.iter_mut() {
+            *key = split_mix(&mut state);
+        }
+        Self {
+            bits,
+            half: bits.div_ceil(2),
+            keys,
+        }
+    }
+
+    #[inline]
+    pub fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    /// The greatest ID it permutes, `2^bits - 1`.
+    #[inline]
+    pub fn max_value(&self) -> i64 {
+        ((1u64 << self.bits) - 1) as i64
+    }
+
+    /// Scramble `id`, which has to be in `[0, 2^bits)`.
+    pub fn encode(&self, id: i64) -> Result<i64> {
+        self.walk(id, |x| self.encrypt(x))
+    }
+
+    /// Recover the ID scrambled into `id`.
+    pub fn decode(&self, id: i64) -> Result<i64> {
+        self.walk(id, |x| self.decrypt(x))
+    }
+
+    /// Apply `permute` until the result is in range again, the network permutes one bit
+    /// more than `bits` if it's odd.
+    fn walk(&self, id: i64, permute: impl Fn(u64) -> u64) -> Result<i64> {
+        if id < 0 || id > self.max_value() {
+            return Err(Error::InvalidId(format!(
+                "{} is out of {} bits",
+                id, self.bits
+            )));
+        }
+        let mut x = permute(id as u64);
+        while x > self.max_value() as u64 {
+            x = permute(x);
+        }
+        Ok(x as i64)
+    }
+
+    #[inline]
+    fn mask(&self) -> u64 {
+        (1u64 << self.half) - 1
+    }
+
+    fn encrypt(&self, x: u64) -> u64 {
+        let (mut left, mut right) = (x >> self.half, x & self.mask());
+        for key in self.keys.iter() {
+            let next = left ^ (round(*key, right) & self.mask());
+            left = right;
+            right = next;
+        }
+        (left << self.half) | right
+    }
+
+    fn decrypt(&self, x: u64) -> u64 {
+        let (mut left, mut right) = (x >> self.half, x & self.mask());
+        for key in self.keys.iter().rev() {
+            let prev = right ^ (round(*key, left) & self.mask());
+            right = left;
+            left = prev;
+        }
+        (left << self.half) | right
+    }
+}
+
+/// Round function, the finalizer of MurmurHash3.
+#[inline]
+fn round(key: u64, half: u64) -> u64 {
+    let mut x = half ^ key;
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    x ^= x >> 33;
+    x = x.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    x ^ (x >> 33)
+}
+
+/// Round keys are drawn from the secret by SplitMix64.
+fn split_mix(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut x = *state;
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
@@ -0,0 +1,149 @@
+//! Sequences starting over every day, month or year, like order numbers `20261017-000123`.
+//!
+//! An ID is the period it's handed out in followed by `digits` digits of a counter, e.g.
+//! `20261017000123`. The counter lives in the leaf of the tag: `max_id` only grows, so
+//! instances agree on a new period by raising it to the period's first ID, and no leaf is
+//! created per period.
+//!
+//! # Design
+//! A sequence per period could be kept under a key of its own, combining the tag and
+//! the period. Encoding the period into the IDs of a single leaf is chosen instead:
+//! every DAO and the storage schema work unchanged, and no leaves pile up as periods
+//! pass. The period is read back from an ID with [`Periodic::split`].
+//!
+//! # Capacity
+//! A period holds `10^digits` IDs for all instances together, counting the ranges they
+//! cached but never handed out, which are lost once the period is over. A refill takes up
+//! to [`max_step`](crate::segment::Config::max_step) IDs, and each instance loads the next
+//! segment ahead, so with the default `max_step` of 1_000_000 a single refill may use up
+//! a whole day of the default 6 digits. Keep `max_step` well below `10^digits` divided by
+//! twice the number of instances. [`Error::TagExhausted`](crate::Error::TagExhausted) is
+//! returned once the period is used up, until the next one starts.
+//!
+//! # Examples
+//! ```no_run
+//! # async fn run() -> leaves::Result<()> {
+//! use std::sync::Arc;
+//! use leaves::dao::MockLeafDao;
+//! use leaves::periodic::{Periodic, Tz};
+//! use leaves::segment::Config;
+//! use leaves::{Leaf, LeafDao, SegmentIDGen};
+//!
+//! let dao = Arc::new(MockLeafDao::default());
+//! dao.insert(Leaf { tag: 1, max_id: 0, step: 100 }).await?;
+//! let periodic = Periodic::daily(Tz::Asia__Shanghai);
+//! let mut service = SegmentIDGen::new(dao, Config::new());
+//! service.set_tag_partition(1, periodic);
+//! service.init().await?;
+//! // like `20261017-000000`
+//! println!("{}", periodic.format(service.get(1).await?));
+//! # Ok(())
+//! # }
+//! ```
+use std::time::SystemTime;
+
+use chrono::{DateTime, Datelike, Utc};
+
+use crate::segment::Partition;
+
+pub use chrono_tz::Tz;
+
+/// How long a sequence lasts.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Period {
+    /// `yyyymmdd`
+    Day,
+    /// `yyyymm`
+    Month,
+    /// `yyyy`
+    Year,
+}
+
+/// A [`Partition`] into periods in a time zone.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Periodic {
+    period: Period,
+    time_zone: Tz,
+    digits: u32,
+}
+
+impl Periodic {
+    /// Counters have 6 digits.
+    pub fn new(period: Period, time_zone: Tz) -> Self {
+        Self {
+            period,
+            time_zone,
+            digits: 6,
+        }
+    }
+
+    #[inline]
+    pub fn daily(time_zone: Tz) -> Self {
+        Self::new(Period::Day, time_zone)
+    }
+
+    #[inline]
+    pub fn monthly(time_zone: Tz) -> Self {
+        Self::new(Period::Month, time_zone)
+    }
+
+    #[inline]
+    pub fn yearly(time_zone: Tz) -> Self {
+        Self::new(Period::Year, time_zone)
+    }
+
+    /// Digits of counters, a period hands out `10^digits` IDs at most.
+    ///
+    /// # Panics
+    /// If `digits` isn't in `1..=10`, IDs would overflow `i64` otherwise.
+    pub fn set_digits(mut self, digits: u32) -> Self {
+        assert!(
+            (1..=10).contains(&digits),
+            "digits must be in 1..=10, got {}",
+            digits
+        );
+        self.digits = digits;
+        self
+    }
+
+    /// The period `now` falls in, like `20261017` for a day.
+    pub fn period(&self, now: SystemTime) -> i64 {
+        let now = DateTime::<Utc>::from(now).with_timezone(&self.time_zone);
+        let (year, month, day) = (now.year() as i64, now.month() as i64, now.day() as i64);
+        match self.period {
+            Period::Day => year * 10000 + month * 100 + day,
+            Period::Month => year * 100 + month,
+            Period::Year => year,
+        }
+    }
+
+    /// Split `id` into its period and counter.
+    #[inline]
+    pub fn split(&self, id: i64) -> (i64, i64) {
+        let scale = self.scale();
+        (id / scale, id % scale)
+    }
+
+    /// `{period}-{counter}`, with the counter padded to `digits` digits.
+    pub fn format(&self, id: i64) -> String {
+        let (period, counter) = self.split(id);
+        format!(
+            "{}-{:0width$}",
+            period,
+            counter,
+            width = self.digits as usize
+        )
+    }
+
+    #[inline]
+    fn scale(&self) -> i64 {
+        10i64.pow(self.digits)
+    }
+}
+
+impl Partition for Periodic {
+    fn block(&self, now: SystemTime) -> (i64, i64) {
+        let start = self.period(now) * self.scale();
+        (start, start + self.scale())
+    }
+}
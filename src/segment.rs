@@ -1,33 +1,122 @@
+use std::collections::HashMap;
 use std::fmt;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
 use async_mutex::{Mutex, MutexGuardArc};
 use dashmap::DashMap;
 
-use crate::{Error, LeafDao, Result};
+use crate::clock::{Clock, SystemClock};
+use crate::codec::{Codec, Decimal};
+use crate::obfuscate::Feistel;
+use crate::{metrics, Error, FreeRange, Leaf, LeafDao, Result};
 
 use super::utils;
 use event_listener::Event;
 
 type Cache = Arc<DashMap<i32, Arc<Mutex<SegmentBuffer>>>>;
 
-pub struct SegmentIDGen<D> {
+/// Splits the IDs of a tag into consecutive blocks, handing out IDs of the block the
+/// current time falls in only. A tag starts over from the beginning of a block once it's
+/// entered, like a sequence reset daily by `periodic::Periodic`.
+pub trait Partition: fmt::Debug + Send + Sync {
+    /// IDs `[start, end)` of the block `now` falls in
+    fn block(&self, now: SystemTime) -> (i64, i64);
+}
+
+pub struct SegmentIDGen<D: ?Sized> {
     dao: Arc<D>,
+    clock: Arc<dyn Clock>,
     init_ok: bool,
+    shut_down: Arc<AtomicBool>,
     cache: Cache,
     config: Config,
+    tag_configs: HashMap<i32, Config>,
+    partitions: HashMap<i32, Arc<dyn Partition>>,
+    obfuscators: HashMap<i32, Feistel>,
+    codecs: HashMap<i32, Arc<dyn Codec>>,
 }
 
-impl<D: 'static + LeafDao + Send + Sync> SegmentIDGen<D> {
+impl<D: 'static + LeafDao + Send + Sync + ?Sized> SegmentIDGen<D> {
     pub fn new(dao: Arc<D>, config: Config) -> Self {
         Self {
             dao,
+            clock: Arc::new(SystemClock),
             init_ok: false,
+            shut_down: Arc::new(AtomicBool::new(false)),
             cache: Arc::new(DashMap::new()),
             config,
+            tag_configs: HashMap::new(),
+            partitions: HashMap::new(),
+            obfuscators: HashMap::new(),
+            codecs: HashMap::new(),
+        }
+    }
+
+    /// Override the config of a specific tag, only `is_strict`, `max_step`,
+    /// `segment_duration`, `max_value` and `warn_percent` take effect. `reuse_free_ranges` is always the generator's.
+    pub fn set_tag_config(&mut self, tag: i32, config: Config) {
+        self.tag_configs.insert(tag, config);
+    }
+
+    /// Hand out IDs of `tag` block by block, see [`Partition`].
+    pub fn set_tag_partition(&mut self, tag: i32, partition: impl Partition + 'static) {
+        self.partitions.insert(tag, Arc::new(partition));
+    }
+
+    /// Scramble IDs of `tag` by `feistel`, [`deobfuscate`](SegmentIDGen::deobfuscate) gets
+    /// them back. IDs handed out are unique but no longer increasing, and the tag is
+    /// exhausted once IDs allocated don't fit in the bits of `feistel`.
+    pub fn set_tag_obfuscator(&mut self, tag: i32, feistel: Feistel) {
+        self.obfuscators.insert(tag, feistel);
+    }
+
+    /// The ID allocated before it was scrambled into `id`, `id` itself if `tag`
+    /// isn't obfuscated.
+    pub fn deobfuscate(&self, tag: i32, id: i64) -> Result<i64> {
+        match self.obfuscators.get(&tag) {
+            Some(feistel) => feistel.decode(id),
+            None => Ok(id),
+        }
+    }
+
+    /// Render IDs of `tag` by `codec` in [`get_string`](SegmentIDGen::get_string),
+    /// [`Decimal`] by default.
+    pub fn set_tag_codec(&mut self, tag: i32, codec: impl Codec + 'static) {
+        self.codecs.insert(tag, Arc::new(codec));
+    }
+
+    fn codec_of(&self, tag: i32) -> Arc<dyn Codec> {
+        self.codecs
+            .get(&tag)
+            .cloned()
+            .unwrap_or_else(|| Arc::new(Decimal))
+    }
+
+    /// The ID [`get_string`](SegmentIDGen::get_string) rendered into `s`.
+    pub fn decode_string(&self, tag: i32, s: &str) -> Result<i64> {
+        self.codec_of(tag).decode(s)
+    }
+
+    /// Replace the clock telling how long segments last, [`SystemClock`] by default.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    fn config_of(&self, tag: i32) -> Config {
+        let mut config = match self.tag_configs.get(&tag) {
+            Some(config) => Config {
+                reuse_free_ranges: self.config.reuse_free_ranges,
+                ..*config
+            },
+            None => self.config,
+        };
+        if let Some(feistel) = self.obfuscators.get(&tag) {
+            let max = feistel.max_value();
+            config.max_value = Some(config.max_value.map_or(max, |max_value| max_value.min(max)));
         }
+        config
     }
 
     pub async fn init(&mut self) -> Result<()> {
@@ -44,69 +133,186 @@ impl<D: 'static + LeafDao + Send + Sync> SegmentIDGen<D> {
     ///
     /// # Examples
     /// ```no_run
+    /// # async fn run() -> leaves::Result<()> {
     /// use leaves::{SegmentIDGen, Leaf, LeafDao};
     /// use std::sync::Arc;
     /// use leaves::dao::MockLeafDao;
     /// use leaves::segment::Config;
     ///
     /// let dao = Arc::new(MockLeafDao::default());
-    /// dao.insert(Leaf {tag: 1, max_id: 1000, step: 1000});
+    /// dao.insert(Leaf {tag: 1, max_id: 1000, step: 1000}).await?;
     /// let service = SegmentIDGen::new(dao, Config::new());
     /// for _ in 0..100 {
     ///     service.get(1).await.unwrap();
     /// }
+    /// # Ok(())
+    /// # }
     /// ```
     pub async fn get(&self, tag: i32) -> Result<i64> {
-        if !self.init_ok {
+        if !self.init_ok || self.shut_down.load(Ordering::Acquire) {
             return Err(Error::ServiceNotReady);
         }
+        let mut config = self.config_of(tag);
         let mut buffer = self.get_segment_buffer(tag).await?.lock_arc().await;
+        if let Some(partition) = self.partitions.get(&tag) {
+            let (result, entered) =
+                Self::enter_block(&*self.dao, &*self.clock, &**partition, buffer, &mut config)
+                    .await;
+            buffer = entered;
+            result?;
+        }
         if !buffer.init_ok {
             tracing::info!("Init Buffer[{}]", tag);
-            Self::update_segment_from_db(self.dao.clone(), &mut buffer, false, true, self.config)
-                .await?;
+            Self::update_segment_from_db(
+                self.dao.clone(),
+                &*self.clock,
+                &mut buffer,
+                false,
+                true,
+                config,
+            )
+            .await?;
         }
-        Self::get_id_from_segment_buffer(self.dao.clone(), self.config, buffer)
-            .await
-            .0
+        let id =
+            Self::get_id_from_segment_buffer(self.dao.clone(), self.clock.clone(), config, buffer)
+                .await
+                .0;
+        metrics::record_get(tag, &id);
+        match self.obfuscators.get(&tag) {
+            Some(feistel) => id.and_then(|id| feistel.encode(id)),
+            None => id,
+        }
+    }
+
+    /// Get an ID rendered by the codec of `tag`, see [`set_tag_codec`](SegmentIDGen::set_tag_codec).
+    pub async fn get_string(&self, tag: i32) -> Result<String> {
+        let id = self.get(tag).await?;
+        self.codec_of(tag).encode(id)
     }
 
     /// Update from database
     pub async fn update(&self, tag: i32) -> Result<()> {
+        if self.shut_down.load(Ordering::Acquire) {
+            return Err(Error::ServiceNotReady);
+        }
+        let mut config = self.config_of(tag);
         let mut buffer = self.get_segment_buffer(tag).await?.lock_arc().await;
-        Self::update_segment_from_db(self.dao.clone(), &mut buffer, false, false, self.config)
-            .await?;
+        if let Some(partition) = self.partitions.get(&tag) {
+            let (result, entered) =
+                Self::enter_block(&*self.dao, &*self.clock, &**partition, buffer, &mut config)
+                    .await;
+            buffer = entered;
+            result?;
+        }
+        Self::update_segment_from_db(
+            self.dao.clone(),
+            &*self.clock,
+            &mut buffer,
+            false,
+            false,
+            config,
+        )
+        .await?;
         Ok(())
     }
 
+    /// Stop handing out IDs, and take the ranges left in the cache out.
+    ///
+    /// They're pushed to the DAO to be handed out first after restart if
+    /// `reuse_free_ranges` is set, and only returned otherwise. Ranges failed to be
+    /// pushed are lost, the last error is returned.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// # async fn run() -> leaves::Result<()> {
+    /// use leaves::{SegmentIDGen, Leaf, LeafDao};
+    /// use std::sync::Arc;
+    /// use leaves::dao::MockLeafDao;
+    /// use leaves::segment::Config;
+    ///
+    /// let dao = Arc::new(MockLeafDao::default());
+    /// dao.insert(Leaf {tag: 1, max_id: 1000, step: 1000}).await?;
+    /// let mut service = SegmentIDGen::new(dao, Config::new().set_reuse_free_ranges(true));
+    /// service.init().await?;
+    /// service.get(1).await?;
+    /// // 1001..2000 is handed out by the next instance
+    /// let ranges = service.shutdown().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn shutdown(&self) -> Result<Vec<FreeRange>> {
+        tracing::info!("Shut down ...");
+        self.shut_down.store(true, Ordering::Release);
+        let buffers = self
+            .cache
+            .iter()
+            .map(|e| e.value().clone())
+            .collect::<Vec<_>>();
+        let mut ranges = vec![];
+        for buffer in buffers {
+            // the segment loaded in background would be lost
+            let mut buffer = Self::wait_bg_task(buffer.lock_arc().await).await;
+            ranges.extend(buffer.drain());
+        }
+        if self.config.reuse_free_ranges {
+            let mut result = Ok(());
+            for range in &ranges {
+                if let Err(err) =
+                    metrics::time_dao("push_free_range", self.dao.push_free_range(*range)).await
+                {
+                    tracing::error!(
+                        "Push range {}..{} of tag[{}] failed: {}",
+                        range.start,
+                        range.end,
+                        range.tag,
+                        err
+                    );
+                    result = Err(err);
+                }
+            }
+            result?;
+        }
+        Ok(ranges)
+    }
+
     /// Remove from cache, useful in lazy mode.
     pub async fn remove(&self, tag: i32) -> bool {
-        self.cache.remove(&tag)
+        self.cache.remove(&tag).is_some()
     }
 
     /// Get a guard protecting a specific tag, which means it could be used to allocate ID multiple times.
     ///
     /// # Examples
     /// ```no_run
+    /// # async fn run() -> leaves::Result<()> {
     /// use leaves::{SegmentIDGen, Leaf, LeafDao};
     /// use std::sync::Arc;
     /// use leaves::dao::MockLeafDao;
     /// use leaves::segment::Config;
     ///
     /// let dao = Arc::new(MockLeafDao::default());
-    /// dao.insert(Leaf {tag: 1, max_id: 1000, step: 1000});
+    /// dao.insert(Leaf {tag: 1, max_id: 1000, step: 1000}).await?;
     /// let service = SegmentIDGen::new(dao, Config::new());
     /// let mut tag_guard = service.get_tag_guard(1).await?;
     /// for _ in 0..100 {
     ///     tag_guard.get().await.unwrap();
     /// }
+    /// # Ok(())
+    /// # }
     /// ```
     pub async fn get_tag_guard(&self, tag: i32) -> Result<SegmentIDGenTagGuard<D>> {
+        if self.shut_down.load(Ordering::Acquire) {
+            return Err(Error::ServiceNotReady);
+        }
         let buffer = self.get_segment_buffer(tag).await?.lock_arc().await;
         let guard = SegmentIDGenTagGuard {
             dao: self.dao.clone(),
+            clock: self.clock.clone(),
             buffer: Some(buffer),
-            config: self.config,
+            config: self.config_of(tag),
+            partition: self.partitions.get(&tag).cloned(),
+            obfuscator: self.obfuscators.get(&tag).copied(),
+            codec: self.codec_of(tag),
         };
 
         Ok(guard)
@@ -135,10 +341,14 @@ impl<D: 'static + LeafDao + Send + Sync> SegmentIDGen<D> {
         }
         let cache = self.cache.clone();
         let dao = self.dao.clone();
+        let shut_down = self.shut_down.clone();
         let interval = self.config.update_cache_interval;
         utils::spawn(async move {
             loop {
                 utils::sleep(interval).await;
+                if shut_down.load(Ordering::Acquire) {
+                    break;
+                }
                 if let Err(err) = Self::update_cache_from_db(cache.clone(), dao.clone()).await {
                     tracing::error!("Update cache failed: {}", err);
                 }
@@ -148,7 +358,7 @@ impl<D: 'static + LeafDao + Send + Sync> SegmentIDGen<D> {
 
     async fn update_cache_from_db(cache: Cache, dao: Arc<D>) -> Result<()> {
         tracing::info!("Update cache with database");
-        let db_tags = dao.tags().await?;
+        let db_tags = metrics::time_dao("tags", dao.tags()).await?;
         if db_tags.is_empty() {
             return Ok(());
         }
@@ -172,37 +382,90 @@ impl<D: 'static + LeafDao + Send + Sync> SegmentIDGen<D> {
         Ok(())
     }
 
+    /// Wait until the next segment isn't being loaded.
+    async fn wait_bg_task(
+        mut buffer: MutexGuardArc<SegmentBuffer>,
+    ) -> MutexGuardArc<SegmentBuffer> {
+        while buffer.bg_task_running.load(Ordering::Acquire) {
+            let listener = buffer.bg_task_finished.listen();
+            let buffer_mutex = MutexGuardArc::source(&buffer).clone();
+            drop(buffer);
+            listener.await;
+            buffer = buffer_mutex.lock_arc().await;
+        }
+        buffer
+    }
+
+    /// Limit `config` to the block of `partition` the time falls in,
+    /// and start over from its beginning if it's just entered.
+    async fn enter_block(
+        dao: &D,
+        clock: &dyn Clock,
+        partition: &dyn Partition,
+        buffer: MutexGuardArc<SegmentBuffer>,
+        config: &mut Config,
+    ) -> (Result<()>, MutexGuardArc<SegmentBuffer>) {
+        let (start, end) = partition.block(clock.system_time());
+        let last = end - 1;
+        config.max_value = Some(config.max_value.map_or(last, |max| max.min(last)));
+        if buffer.floor >= start {
+            return (Ok(()), buffer);
+        }
+        // or the segment loaded in background would be handed out after the new block
+        let mut buffer = Self::wait_bg_task(buffer).await;
+        if buffer.floor < start {
+            let tag = buffer.tag;
+            tracing::info!("Buffer[{}] starts over from {}", tag, start);
+            let result = metrics::time_dao(
+                "set_max_id_if_greater",
+                dao.set_max_id_if_greater(tag, start),
+            )
+            .await;
+            if let Err(err) = result {
+                return (Err(err), buffer);
+            }
+            buffer.floor = start;
+            buffer.exhausted = false;
+            buffer.init_ok = false;
+            buffer.next_ready = false;
+        }
+        (Ok(()), buffer)
+    }
+
     async fn get_id_from_segment_buffer(
         dao: Arc<D>,
+        clock: Arc<dyn Clock>,
         config: Config,
         mut buffer: MutexGuardArc<SegmentBuffer>,
     ) -> (Result<i64>, MutexGuardArc<SegmentBuffer>) {
         let tag = buffer.tag;
         let segment = buffer.current();
         if !buffer.next_ready
-            && (segment.idle() < (segment.step * 9 / 10) as i64)
-            && !buffer
+            && !buffer.exhausted
+            && (segment.idle() < segment.step as i64 * 9 / 10)
+            && buffer
                 .bg_task_running
-                .compare_and_swap(false, true, Ordering::Acquire)
+                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
         {
             let buffer_mutex = MutexGuardArc::source(&buffer).clone();
+            let (dao, clock) = (dao.clone(), clock.clone());
             utils::spawn(async move {
                 let mut buffer = buffer_mutex.lock_arc().await;
-                if Self::update_segment_from_db(dao, &mut buffer, true, false, config)
+                if Self::update_segment_from_db(dao, &*clock, &mut buffer, true, false, config)
                     .await
                     .is_ok()
                 {
                     tracing::info!("Update Buffer[{}]'s next segment from DB", tag);
                     buffer.next_ready = true;
+                } else {
+                    metrics::record_preload_failure(tag);
                 }
                 buffer.bg_task_running.store(false, Ordering::Release);
-                buffer.bg_task_finished.notify(std::usize::MAX);
+                buffer.bg_task_finished.notify(usize::MAX);
             });
         }
-        let segment = buffer.current_mut();
-        let val = segment.val;
-        segment.val += 1;
-        if val < segment.max {
+        if let Some(val) = buffer.take_id(config.is_strict) {
             (Ok(val), buffer)
         } else {
             // waits until background task finished
@@ -215,18 +478,34 @@ impl<D: 'static + LeafDao + Send + Sync> SegmentIDGen<D> {
             } else {
                 buffer
             };
-            let segment = buffer.current_mut();
-            let val = segment.val;
-            segment.val += 1;
-            if val < segment.max {
+            if let Some(val) = buffer.take_id(config.is_strict) {
                 (Ok(val), buffer)
             } else if buffer.next_ready {
                 tracing::info!("Buffer[{}] switched", tag);
+                metrics::record_switch(tag);
                 buffer.switch();
                 buffer.next_ready = false;
-                let val = buffer.current_mut().val;
-                buffer.current_mut().val += 1;
-                (Ok(val), buffer)
+                // only in strict mode, a new segment is never empty
+                loop {
+                    if let Some(val) = buffer.take_id(config.is_strict) {
+                        break (Ok(val), buffer);
+                    }
+                    tracing::warn!("Buffer[{}] skipped a segment behind the last ID", tag);
+                    let result = Self::update_segment_from_db(
+                        dao.clone(),
+                        &*clock,
+                        &mut buffer,
+                        false,
+                        false,
+                        config,
+                    )
+                    .await;
+                    if let Err(err) = result {
+                        break (Err(err), buffer);
+                    }
+                }
+            } else if buffer.exhausted {
+                (Err(Error::TagExhausted), buffer)
             } else {
                 (Err(Error::BothSegmentsNotReady), buffer)
             }
@@ -235,6 +514,7 @@ impl<D: 'static + LeafDao + Send + Sync> SegmentIDGen<D> {
 
     async fn update_segment_from_db(
         dao: Arc<D>,
+        clock: &dyn Clock,
         buffer: &mut MutexGuardArc<SegmentBuffer>,
         is_next: bool,
         is_init: bool,
@@ -243,17 +523,38 @@ impl<D: 'static + LeafDao + Send + Sync> SegmentIDGen<D> {
         if is_init && buffer.init_ok {
             return Ok(());
         }
-        let leaf = if !buffer.init_ok {
-            let leaf = dao.update_max(buffer.tag).await?;
+        if config.reuse_free_ranges
+            && Self::update_segment_from_free_range(&*dao, clock, buffer, is_next, config).await?
+        {
+            return Ok(());
+        }
+        // `step` is the size of the range just allocated
+        let (leaf, step) = if !buffer.init_ok {
+            let (leaf, step) = Self::allocate(&*dao, buffer, None, config).await?;
             buffer.step = leaf.step;
             buffer.min_step = leaf.step;
             buffer.init_ok = true;
-            buffer.updated_at = Instant::now();
-            leaf
+            buffer.updated_at = clock.now();
+            (leaf, step)
         } else {
-            let duration = buffer.updated_at.elapsed();
+            // the step set in database takes effect from this refill
+            let db_step = metrics::time_dao("leaf", dao.leaf(buffer.tag)).await?.step;
+            let changed = db_step != buffer.min_step;
+            if changed {
+                tracing::info!(
+                    "Buffer[{}] step changed from {} to {}",
+                    buffer.tag,
+                    buffer.min_step,
+                    db_step
+                );
+                buffer.step = db_step;
+                buffer.min_step = db_step;
+            }
+            let duration = clock.now().saturating_duration_since(buffer.updated_at);
             let step = buffer.step;
-            let next_step = if duration < config.segment_duration && step * 2 <= config.max_step {
+            let next_step = if changed {
+                step
+            } else if duration < config.segment_duration && step <= config.max_step / 2 {
                 step * 2
             } else if duration >= config.segment_duration * 2 && step / 2 >= buffer.min_step {
                 step / 2
@@ -267,54 +568,226 @@ impl<D: 'static + LeafDao + Send + Sync> SegmentIDGen<D> {
                 duration.as_millis(),
                 next_step
             );
-            let leaf = dao.update_max_by_step(buffer.tag, next_step).await?;
-            buffer.updated_at = Instant::now();
-            buffer.step = next_step;
+            let (leaf, _) = Self::allocate(&*dao, buffer, Some(next_step), config).await?;
+            buffer.updated_at = clock.now();
+            if leaf.step != buffer.min_step {
+                // step changed in database since it was read, start over from it next time
+                tracing::info!(
+                    "Buffer[{}] step changed from {} to {}",
+                    buffer.tag,
+                    buffer.min_step,
+                    leaf.step
+                );
+                buffer.step = leaf.step;
+            } else {
+                buffer.step = next_step;
+            }
             buffer.min_step = leaf.step;
-            leaf
+            (leaf, next_step)
         };
-        let step = buffer.step;
+        metrics::record_step(buffer.tag, buffer.step);
+        let max = config.max_value.map_or(leaf.max_id, |max_value| {
+            leaf.max_id.min(max_value.saturating_add(1))
+        });
+        if let Some(max_value) = config.max_value {
+            // of the current block if partitioned, IDs below it belong to past ones
+            let start = buffer.floor.max(0);
+            let usage = (max - start) as f64 / (max_value as f64 + 1.0 - start as f64);
+            metrics::record_usage(buffer.tag, usage);
+            if usage * 100.0 >= config.warn_percent as f64 {
+                tracing::warn!(
+                    "Tag[{}] allocated {:.1}% of IDs up to {}",
+                    buffer.tag,
+                    usage * 100.0,
+                    max_value
+                );
+            }
+        }
         let segment = if is_next {
             buffer.next_mut()
         } else {
             buffer.current_mut()
         };
         segment.val = leaf.max_id - step as i64;
-        segment.max = leaf.max_id;
+        segment.max = max;
         segment.step = step;
         Ok(())
     }
+
+    /// Allocate `step` IDs, or as many as the step in database if `None`, without passing
+    /// `max_value`. Returns the leaf updated and the number of IDs allocated.
+    async fn allocate(
+        dao: &D,
+        buffer: &mut SegmentBuffer,
+        step: Option<i32>,
+        config: Config,
+    ) -> Result<(Leaf, i32)> {
+        let tag = buffer.tag;
+        let result = match (config.max_value, step) {
+            (None, None) => metrics::time_dao("update_max", dao.update_max(tag))
+                .await
+                .map(|leaf| (leaf, leaf.step)),
+            (None, Some(step)) => {
+                metrics::time_dao("update_max_by_step", dao.update_max_by_step(tag, step))
+                    .await
+                    .map(|leaf| (leaf, step))
+            }
+            (Some(max_value), step) => {
+                let step = match step {
+                    Some(step) => step,
+                    None => metrics::time_dao("leaf", dao.leaf(tag)).await?.step,
+                };
+                metrics::time_dao(
+                    "update_max_by_step_up_to",
+                    dao.update_max_by_step_up_to(tag, step, max_value),
+                )
+                .await
+                .map(|leaf| (leaf, step))
+            }
+        };
+        match result {
+            Ok(_) => buffer.exhausted = false,
+            Err(Error::TagExhausted) => {
+                tracing::warn!("Tag[{}] exhausted", tag);
+                buffer.exhausted = true;
+            }
+            Err(_) => {}
+        }
+        result
+    }
+
+    /// Load a range left by an instance shut down, `false` if there's none to take.
+    async fn update_segment_from_free_range(
+        dao: &D,
+        clock: &dyn Clock,
+        buffer: &mut MutexGuardArc<SegmentBuffer>,
+        is_next: bool,
+        config: Config,
+    ) -> Result<bool> {
+        let tag = buffer.tag;
+        // read before the range is taken away, so it's not lost if that fails
+        let leaf = if buffer.init_ok {
+            None
+        } else {
+            Some(metrics::time_dao("leaf", dao.leaf(tag)).await?)
+        };
+        let mut range = match metrics::time_dao("pop_free_range", dao.pop_free_range(tag)).await? {
+            Some(range) => range,
+            None => return Ok(false),
+        };
+        range.start = range.start.max(buffer.floor);
+        if let Some(max_value) = config.max_value {
+            range.end = range.end.min(max_value.saturating_add(1));
+        }
+        if range.start >= range.end {
+            tracing::warn!(
+                "Buffer[{}] dropped a range out of {}..={}",
+                tag,
+                buffer.floor,
+                config.max_value.unwrap_or(i64::MAX)
+            );
+            return Ok(false);
+        }
+        if let Some(last_id) = buffer.last_id.filter(|_| config.is_strict) {
+            if range.end <= last_id + 1 {
+                // it would be skipped as a whole, leave it to other instances
+                metrics::time_dao("push_free_range", dao.push_free_range(range)).await?;
+                return Ok(false);
+            }
+        }
+        if let Some(leaf) = leaf {
+            buffer.step = leaf.step;
+            buffer.min_step = leaf.step;
+            buffer.init_ok = true;
+            buffer.updated_at = clock.now();
+        }
+        tracing::info!(
+            "Buffer[{}] reuses range {}..{}",
+            tag,
+            range.start,
+            range.end
+        );
+        let segment = if is_next {
+            buffer.next_mut()
+        } else {
+            buffer.current_mut()
+        };
+        segment.val = range.start;
+        segment.max = range.end;
+        segment.step = (range.end - range.start).min(i32::MAX as i64) as i32;
+        Ok(true)
+    }
 }
 
 /// An owned IDGen guarding a specific tag.
 /// It's like `MutexGuard`, there can only be one guard of a tag at a time.
-pub struct SegmentIDGenTagGuard<D> {
+pub struct SegmentIDGenTagGuard<D: ?Sized> {
     dao: Arc<D>,
+    clock: Arc<dyn Clock>,
     buffer: Option<MutexGuardArc<SegmentBuffer>>,
     config: Config,
+    partition: Option<Arc<dyn Partition>>,
+    obfuscator: Option<Feistel>,
+    codec: Arc<dyn Codec>,
 }
 
-impl<D: 'static + LeafDao + Send + Sync> SegmentIDGenTagGuard<D> {
+impl<D: 'static + LeafDao + Send + Sync + ?Sized> SegmentIDGenTagGuard<D> {
     pub async fn get(&mut self) -> Result<i64> {
         let mut buffer = self.buffer.take().unwrap();
+        let mut config = self.config;
+        if let Some(partition) = &self.partition {
+            let (result, entered) = SegmentIDGen::enter_block(
+                &*self.dao,
+                &*self.clock,
+                &**partition,
+                buffer,
+                &mut config,
+            )
+            .await;
+            buffer = entered;
+            if let Err(err) = result {
+                self.buffer.replace(buffer);
+                return Err(err);
+            }
+        }
         if !buffer.init_ok {
-            SegmentIDGen::update_segment_from_db(
+            let result = SegmentIDGen::update_segment_from_db(
                 self.dao.clone(),
+                &*self.clock,
                 &mut buffer,
                 false,
                 true,
-                self.config,
+                config,
             )
-            .await?;
+            .await;
+            if let Err(err) = result {
+                self.buffer.replace(buffer);
+                return Err(err);
+            }
         }
-        let (id, buffer) =
-            SegmentIDGen::get_id_from_segment_buffer(self.dao.clone(), self.config, buffer).await;
+        let (id, buffer) = SegmentIDGen::get_id_from_segment_buffer(
+            self.dao.clone(),
+            self.clock.clone(),
+            config,
+            buffer,
+        )
+        .await;
+        metrics::record_get(buffer.tag, &id);
         self.buffer.replace(buffer);
-        id
+        match &self.obfuscator {
+            Some(feistel) => id.and_then(|id| feistel.encode(id)),
+            None => id,
+        }
+    }
+
+    /// Get an ID rendered by the codec of the tag.
+    pub async fn get_string(&mut self) -> Result<String> {
+        let id = self.get().await?;
+        self.codec.encode(id)
     }
 }
 
-unsafe impl<D: 'static + LeafDao + Send + Sync> Send for SegmentIDGenTagGuard<D> {}
+unsafe impl<D: 'static + LeafDao + Send + Sync + ?Sized> Send for SegmentIDGenTagGuard<D> {}
 
 #[derive(Debug, Default)]
 pub struct Segment {
@@ -349,11 +822,17 @@ pub struct SegmentBuffer {
     pub init_ok: bool,
     pub next_ready: bool,
     pub tag: i32,
+    /// `max_value` is reached
+    exhausted: bool,
+    /// the least ID to hand out, the start of the current block of a [`Partition`]
+    floor: i64,
     bg_task_running: AtomicBool,
     bg_task_finished: Event,
     updated_at: Instant,
     step: i32,
     min_step: i32,
+    /// the last ID handed out
+    last_id: Option<i64>,
     segments: [Segment; 2],
     current_idx: usize,
 }
@@ -364,11 +843,14 @@ impl SegmentBuffer {
         Self {
             init_ok: false,
             next_ready: false,
+            exhausted: false,
+            floor: i64::MIN,
             bg_task_running: false.into(),
             bg_task_finished: Event::new(),
             updated_at: Instant::now(),
             step: 0,
             min_step: 0,
+            last_id: None,
             tag,
             segments: [Segment::default(), Segment::default()],
             current_idx: 0,
@@ -409,21 +891,89 @@ impl SegmentBuffer {
     pub fn switch(&mut self) {
         self.current_idx = self.next_idx();
     }
+
+    /// Take the ranges not handed out yet, the buffer is loaded from scratch next time.
+    fn drain(&mut self) -> Vec<FreeRange> {
+        let mut ranges = vec![];
+        if self.init_ok {
+            let current = self.current();
+            ranges.push((current.val, current.max));
+        }
+        if self.next_ready {
+            let next = self.next();
+            ranges.push((next.val, next.max));
+        }
+        self.init_ok = false;
+        self.next_ready = false;
+        let tag = self.tag;
+        ranges
+            .into_iter()
+            .filter(|(start, end)| start < end)
+            .map(|(start, end)| FreeRange { tag, start, end })
+            .collect()
+    }
+
+    /// Take an ID from the current segment, `None` if it's used up.
+    /// IDs below `floor` are skipped, so are those not greater than the last one if `strict`.
+    fn take_id(&mut self, strict: bool) -> Option<i64> {
+        let mut floor = self.floor;
+        if let Some(last_id) = self.last_id.filter(|_| strict) {
+            floor = floor.max(last_id + 1);
+        }
+        let segment = self.current_mut();
+        segment.val = segment.val.max(floor);
+        let val = segment.val;
+        if val < segment.max {
+            segment.val += 1;
+            self.last_id = Some(val);
+            Some(val)
+        } else {
+            None
+        }
+    }
 }
 
 /// Config of [`SegmentIDGen`]
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+///
+/// Durations are (de)serialized as human readable strings like `15m` or `1h 30m`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
     /// * default(`false`): load all tags from database at startup,
-    /// and update with database every `update_cache_interval`.
+    ///   and update with database every `update_cache_interval`.
     ///
     /// * lazy(`true`): load tags on demand, and needs remove them manually.
     pub is_lazy: bool,
     /// upper bound of step, default is 1_000_000.
     pub max_step: i32,
+    /// * default(`false`): IDs of a tag handed out by an instance increase as long as
+    ///   `max_id` in database only grows, [`SegmentIDGen::update`] isn't called
+    ///   while the next segment is loaded already, and free ranges aren't reused.
+    ///
+    /// * strict(`true`): IDs of a tag handed out by an instance always strictly increase,
+    ///   in the order `get` calls take the tag. IDs not greater than the last one are
+    ///   skipped, so are whole segments behind it, for which new ones are allocated right
+    ///   away. It's forgotten once the tag is removed from the cache.
+    ///
+    /// There is no order between IDs of different instances in either mode, each instance
+    /// hands out a range of its own.
+    pub is_strict: bool,
+    /// hand out ranges left by instances [shut down](SegmentIDGen::shutdown) before
+    /// allocating new ones, so fewer IDs are wasted on restart, default is `false`.
+    /// Only the MySQL, PostgreSQL, SQLite and mock DAOs support free ranges, the others
+    /// fail with [`Error::Unsupported`].
+    pub reuse_free_ranges: bool,
+    /// greatest ID of a tag to hand out, [`Error::TagExhausted`] is returned after it.
+    /// `max_id` in database isn't updated past it either, unlimited by default.
+    pub max_value: Option<i64>,
+    /// percentage of `max_value` allocated past which a warning is logged on every
+    /// refilling, default is 80. It's of the current block for a partitioned tag.
+    pub warn_percent: u8,
     /// related to generate next step, default is 15min.
+    #[serde(with = "humantime_serde")]
     pub segment_duration: Duration,
     /// default is 1min.
+    #[serde(with = "humantime_serde")]
     pub update_cache_interval: Duration,
 }
 
@@ -431,6 +981,10 @@ impl Default for Config {
     fn default() -> Self {
         Self {
             is_lazy: false,
+            is_strict: false,
+            reuse_free_ranges: false,
+            max_value: None,
+            warn_percent: 80,
             max_step: 1_000_000,
             segment_duration: Duration::from_secs(15 * 60),
             update_cache_interval: Duration::from_secs(60),
@@ -451,11 +1005,31 @@ impl Config {
         }
     }
     #[inline]
+    pub fn set_strict(mut self, strict: bool) -> Self {
+        self.is_strict = strict;
+        self
+    }
+    #[inline]
+    pub fn set_reuse_free_ranges(mut self, reuse: bool) -> Self {
+        self.reuse_free_ranges = reuse;
+        self
+    }
+    #[inline]
     pub fn set_max_step(mut self, step: i32) -> Self {
         self.max_step = step;
         self
     }
     #[inline]
+    pub fn set_max_value(mut self, max_value: i64) -> Self {
+        self.max_value = Some(max_value);
+        self
+    }
+    #[inline]
+    pub fn set_warn_percent(mut self, percent: u8) -> Self {
+        self.warn_percent = percent;
+        self
+    }
+    #[inline]
     pub fn set_segment_duration(mut self, duration: Duration) -> Self {
         self.segment_duration = duration;
         self
@@ -24,3 +24,66 @@ pub(crate) async fn sleep(duration: Duration) {
         }
     }
 }
+
+/// Wait for `future` at most `duration`, `None` if it takes longer.
+#[cfg(any(feature = "redis", feature = "client"))]
+pub(crate) async fn timeout<F: Future>(duration: Duration, future: F) -> Option<F::Output> {
+    use futures_util::future::{select, Either};
+
+    futures_util::pin_mut!(future);
+    let sleep = sleep(duration);
+    futures_util::pin_mut!(sleep);
+    match select(future, sleep).await {
+        Either::Left((output, _)) => Some(output),
+        Either::Right(_) => None,
+    }
+}
+
+/// Run blocking work off the async executor.
+#[cfg(any(feature = "file", feature = "sled"))]
+pub(crate) async fn spawn_blocking<F, T>(f: F) -> T
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "runtime-async-std")] {
+            async_std::task::spawn_blocking(f).await
+        } else if #[cfg(feature = "runtime-tokio")] {
+            tokio::task::spawn_blocking(f)
+                .await
+                .expect("blocking task panicked")
+        }
+    }
+}
+
+/// Send a raw request to `address` and read until the peer closes the connection,
+/// failing with [`TimedOut`](std::io::ErrorKind::TimedOut) if it's not done within `duration`.
+#[cfg(feature = "client")]
+pub(crate) async fn round_trip(
+    address: &str,
+    request: &[u8],
+    duration: Duration,
+) -> std::io::Result<Vec<u8>> {
+    let exchange = async {
+        let mut response = vec![];
+        cfg_if::cfg_if! {
+            if #[cfg(feature = "runtime-async-std")] {
+                use async_std::io::prelude::{ReadExt, WriteExt};
+                let mut stream = async_std::net::TcpStream::connect(address).await?;
+                stream.write_all(request).await?;
+                stream.read_to_end(&mut response).await?;
+            } else if #[cfg(feature = "runtime-tokio")] {
+                use tokio::io::{AsyncReadExt, AsyncWriteExt};
+                let mut stream = tokio::net::TcpStream::connect(address).await?;
+                stream.write_all(request).await?;
+                stream.read_to_end(&mut response).await?;
+            }
+        }
+        Ok(response)
+    };
+    timeout(duration, exchange).await.unwrap_or_else(|| {
+        let err = std::io::Error::new(std::io::ErrorKind::TimedOut, "request timed out");
+        Err(err)
+    })
+}
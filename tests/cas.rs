@@ -0,0 +1,97 @@
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use leaves::dao::{CasLeafDao, CasStore, MemoryCasStore};
+use leaves::{Error, Leaf, LeafDao, Result};
+
+/// Fails every other `compare_and_set`, as if someone else always got there first.
+#[derive(Default)]
+struct ConflictingStore {
+    inner: MemoryCasStore,
+    calls: AtomicUsize,
+}
+
+#[async_trait]
+impl CasStore for ConflictingStore {
+    async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.inner.get(key).await
+    }
+
+    async fn compare_and_set(
+        &self,
+        key: &[u8],
+        old: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<bool> {
+        if self.calls.fetch_add(1, Ordering::SeqCst).is_multiple_of(2) {
+            return Ok(false);
+        }
+        self.inner.compare_and_set(key, old, new).await
+    }
+}
+
+#[tokio::test]
+async fn test_cas_dao() {
+    let dao = CasLeafDao::new(ConflictingStore::default());
+    dao.insert(Leaf {
+        tag: 1,
+        max_id: 0,
+        step: 100,
+    })
+    .await
+    .unwrap();
+    dao.insert(Leaf {
+        tag: 2,
+        max_id: 0,
+        step: 10,
+    })
+    .await
+    .unwrap();
+    assert_eq!(dao.update_max(1).await.unwrap().max_id, 100);
+    assert_eq!(dao.update_max_by_step(1, 10).await.unwrap().max_id, 110);
+    assert_eq!(dao.update_step(1, 20).await.unwrap().step, 20);
+    assert_eq!(dao.set_max_id_if_greater(1, 50).await.unwrap().max_id, 110);
+    let overwrite = Leaf {
+        tag: 1,
+        max_id: 0,
+        step: 1,
+    };
+    assert!(matches!(dao.insert(overwrite).await, Err(Error::TagExists)));
+    assert_eq!(dao.leaf(1).await.unwrap().max_id, 110);
+    dao.upsert(overwrite).await.unwrap();
+    assert_eq!(dao.leaf(1).await.unwrap(), overwrite);
+    assert!(matches!(dao.update_max(3).await, Err(Error::TagNotExist)));
+    assert_eq!(dao.tags().await.unwrap(), vec![1, 2]);
+    assert_eq!(dao.leaves().await.unwrap().len(), 2);
+
+    dao.delete(1).await.unwrap();
+    assert!(matches!(dao.delete(1).await, Err(Error::TagNotExist)));
+    assert_eq!(dao.tags().await.unwrap(), vec![2]);
+    assert!(dao.store().calls.load(Ordering::SeqCst) > 10);
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn test_cas_dao_concurrent() {
+    let dao = Arc::new(CasLeafDao::new(MemoryCasStore::default()));
+    dao.insert(Leaf {
+        tag: 1,
+        max_id: 0,
+        step: 10,
+    })
+    .await
+    .unwrap();
+    let tasks = (0..100)
+        .map(|_| {
+            let dao = dao.clone();
+            tokio::spawn(async move { dao.update_max(1).await.unwrap().max_id })
+        })
+        .collect::<Vec<_>>();
+    let mut ends = HashSet::new();
+    for task in tasks {
+        assert!(ends.insert(task.await.unwrap()));
+    }
+    assert_eq!(ends, (1..=100).map(|i| i * 10).collect());
+}
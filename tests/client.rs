@@ -0,0 +1,153 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use leaves::client::RemoteLeafClient;
+use leaves::dao::{MockLeafDao, RemoteLeafDao};
+use leaves::segment::Config;
+use leaves::{Leaf, LeafDao};
+
+/// A minimal leaves server speaking the client protocol, counting range requests.
+async fn serve(dao: Arc<MockLeafDao>, ranges: Arc<AtomicUsize>) -> String {
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (socket, _) = listener.accept().await.unwrap();
+            tokio::spawn(handle(socket, dao.clone(), ranges.clone()));
+        }
+    });
+    format!("http://{}", address)
+}
+
+async fn handle(mut socket: TcpStream, dao: Arc<MockLeafDao>, ranges: Arc<AtomicUsize>) {
+    let mut buf = vec![0; 1024];
+    let mut len = 0;
+    while !buf[..len].windows(4).any(|w| w == b"\r\n\r\n") {
+        len += socket.read(&mut buf[len..]).await.unwrap();
+    }
+    let request = String::from_utf8_lossy(&buf[..len]).to_string();
+    let mut parts = request.split(' ');
+    let (method, path) = (parts.next().unwrap(), parts.next().unwrap());
+    let segments = path.trim_start_matches('/').split('/').collect::<Vec<_>>();
+    let result = match (method, segments.as_slice()) {
+        ("GET", ["tags"]) => dao.tags().await.map(|tags| serde_json::to_vec(&tags)),
+        ("GET", ["leaves", tag]) => dao
+            .leaf(tag.parse().unwrap())
+            .await
+            .map(|leaf| serde_json::to_vec(&leaf)),
+        ("POST", ["leaves", tag, max]) => {
+            ranges.fetch_add(1, Ordering::SeqCst);
+            let tag = tag.parse().unwrap();
+            let query = max
+                .strip_prefix("max?step=")
+                .map(|query| query.split("&up_to="));
+            match query.map(|mut query| (query.next().unwrap(), query.next())) {
+                Some((step, Some(max_value))) => {
+                    let (step, max_value) = (step.parse().unwrap(), max_value.parse().unwrap());
+                    dao.update_max_by_step_up_to(tag, step, max_value).await
+                }
+                Some((step, None)) => dao.update_max_by_step(tag, step.parse().unwrap()).await,
+                None => dao.update_max(tag).await,
+            }
+            .map(|leaf| serde_json::to_vec(&leaf))
+        }
+        _ => unreachable!("unexpected request {}", request),
+    };
+    let response = match result {
+        Ok(body) => {
+            let body = body.unwrap();
+            let mut response =
+                format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", body.len()).into_bytes();
+            response.extend(body);
+            response
+        }
+        Err(leaves::Error::TagExhausted) => {
+            b"HTTP/1.1 409 Conflict\r\nContent-Length: 0\r\n\r\n".to_vec()
+        }
+        Err(_) => b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_vec(),
+    };
+    socket.write_all(&response).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_remote_client() {
+    let dao = Arc::new(MockLeafDao::default());
+    dao.insert(Leaf {
+        tag: 1,
+        max_id: 0,
+        step: 1000,
+    })
+    .await
+    .unwrap();
+    let ranges = Arc::new(AtomicUsize::new(0));
+    let url = serve(dao, ranges.clone()).await;
+    let client = RemoteLeafClient::connect(&url, Config::lazy())
+        .await
+        .unwrap();
+    for expected in 0..1000 {
+        assert_eq!(client.get(1).await.unwrap(), expected);
+    }
+    assert!(ranges.load(Ordering::SeqCst) <= 2);
+    assert!(matches!(
+        client.get(2).await,
+        Err(leaves::Error::TagNotExist)
+    ));
+}
+
+#[tokio::test]
+async fn test_remote_dao() {
+    let dao = Arc::new(MockLeafDao::default());
+    dao.insert(Leaf {
+        tag: 1,
+        max_id: 0,
+        step: 1000,
+    })
+    .await
+    .unwrap();
+    let url = serve(dao, Arc::new(AtomicUsize::new(0))).await;
+    let remote = RemoteLeafDao::new(&url).unwrap();
+    assert_eq!(remote.tags().await.unwrap(), vec![1]);
+    assert_eq!(remote.update_max(1).await.unwrap().max_id, 1000);
+    assert_eq!(remote.update_max_by_step(1, 10).await.unwrap().max_id, 1010);
+    assert_eq!(remote.leaf(1).await.unwrap().max_id, 1010);
+    let leaf = remote.update_max_by_step_up_to(1, 10, 1010).await.unwrap();
+    assert_eq!(leaf.max_id, 1020);
+    assert!(matches!(
+        remote.update_max_by_step_up_to(1, 10, 1010).await,
+        Err(leaves::Error::TagExhausted)
+    ));
+    assert!(matches!(
+        remote.leaf(2).await,
+        Err(leaves::Error::TagNotExist)
+    ));
+
+    let remote = leaves::dao::connect(&url).await.unwrap();
+    assert_eq!(remote.update_max(1).await.unwrap().max_id, 2020);
+}
+
+#[tokio::test]
+async fn test_remote_dao_timeout() {
+    // accepts connections and never answers nor closes them
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        let mut sockets = vec![];
+        loop {
+            let (socket, _) = listener.accept().await.unwrap();
+            sockets.push(socket);
+        }
+    });
+    let remote = RemoteLeafDao::new(&url)
+        .unwrap()
+        .with_timeout(Duration::from_millis(100));
+    let start = Instant::now();
+    match remote.update_max(1).await {
+        Err(leaves::Error::Io(err)) => assert_eq!(err.kind(), std::io::ErrorKind::TimedOut),
+        other => panic!("{:?}", other),
+    }
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
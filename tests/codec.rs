@@ -0,0 +1,119 @@
+use std::sync::Arc;
+
+use leaves::codec::{Base62, Codec, Crockford, Decimal, Hashids};
+use leaves::dao::MockLeafDao;
+use leaves::segment::Config;
+use leaves::{Error, Leaf, LeafDao, SegmentIDGen};
+
+const IDS: [i64; 8] = [0, 1, 61, 62, 12345, 1 << 32, i64::MAX - 1, i64::MAX];
+
+fn round_trip(codec: &dyn Codec) {
+    for &id in IDS.iter() {
+        let encoded = codec.encode(id).unwrap();
+        assert_eq!(codec.decode(&encoded).unwrap(), id, "{:?} {}", codec, id);
+    }
+    assert!(matches!(codec.encode(-1), Err(Error::InvalidId(_))));
+    assert!(matches!(codec.decode(""), Err(Error::InvalidId(_))));
+}
+
+#[test]
+fn test_base62() {
+    round_trip(&Base62);
+    assert_eq!(Base62.encode(0).unwrap(), "0");
+    assert_eq!(Base62.encode(61).unwrap(), "z");
+    assert_eq!(Base62.encode(62).unwrap(), "10");
+    assert_eq!(Base62.encode(i64::MAX).unwrap(), "AzL8n0Y58m7");
+    // case-sensitive
+    assert_ne!(Base62.decode("a").unwrap(), Base62.decode("A").unwrap());
+    assert!(matches!(Base62.decode("a-b"), Err(Error::InvalidId(_))));
+    // overflow
+    assert!(matches!(
+        Base62.decode("AzL8n0Y58m8"),
+        Err(Error::InvalidId(_))
+    ));
+}
+
+#[test]
+fn test_crockford() {
+    round_trip(&Crockford);
+    assert_eq!(Crockford.encode(12345).unwrap(), "C1S");
+    assert_eq!(Crockford.encode(31).unwrap(), "Z");
+    assert_eq!(Crockford.encode(i64::MAX).unwrap(), "7ZZZZZZZZZZZZ");
+    for s in ["c1s", "C-1-S", "CIS", "ClS"].iter() {
+        assert_eq!(Crockford.decode(s).unwrap(), 12345, "{}", s);
+    }
+    assert_eq!(Crockford.decode("1o").unwrap(), 32);
+    for s in ["U", "C1S!", "-", "8ZZZZZZZZZZZZ"].iter() {
+        assert!(
+            matches!(Crockford.decode(s), Err(Error::InvalidId(_))),
+            "{}",
+            s
+        );
+    }
+}
+
+#[test]
+fn test_hashids() {
+    let hashids = Hashids::new("this is my salt", 0);
+    round_trip(&hashids);
+    assert_eq!(hashids.encode(12345).unwrap(), "NkK9");
+    assert_eq!(hashids.decode("NkK9").unwrap(), 12345);
+    // not encoded by this salt
+    assert!(matches!(hashids.decode("NkK8"), Err(Error::InvalidId(_))));
+    assert!(Hashids::new("another salt", 0).decode("NkK9").is_err());
+
+    let padded = Hashids::new("this is my salt", 8);
+    round_trip(&padded);
+    assert_eq!(padded.encode(1).unwrap(), "gB0NV05e");
+    assert_eq!(padded.decode("gB0NV05e").unwrap(), 1);
+    let unsalted = Hashids::new("", 16);
+    assert_eq!(unsalted.encode(1).unwrap(), "4q2VolejRejNmGQB");
+    for &id in IDS.iter() {
+        assert!(unsalted.encode(id).unwrap().len() >= 16);
+    }
+
+    let custom = Hashids::with_alphabet("salt", 0, "0123456789abcdef").unwrap();
+    round_trip(&custom);
+    assert!(custom
+        .encode(12345)
+        .unwrap()
+        .bytes()
+        .all(|c| c.is_ascii_hexdigit()));
+    for alphabet in ["0123456789abcde", "0123456789abcdef ", "0123456789abcdeé"].iter() {
+        assert!(matches!(
+            Hashids::with_alphabet("salt", 0, alphabet),
+            Err(Error::InvalidConfig(_))
+        ));
+    }
+}
+
+#[tokio::test]
+async fn test_get_string() {
+    let dao = Arc::new(MockLeafDao::default());
+    dao.clear_latency();
+    for tag in 1..=3 {
+        dao.insert(Leaf {
+            tag,
+            max_id: 12345,
+            step: 10,
+        })
+        .await
+        .unwrap();
+    }
+    let mut service = SegmentIDGen::new(dao, Config::new().set_max_step(10));
+    service.set_tag_codec(1, Base62);
+    service.set_tag_codec(2, Hashids::new("this is my salt", 0));
+    service.init().await.unwrap();
+
+    assert_eq!(service.get_string(1).await.unwrap(), "3D7");
+    assert_eq!(service.decode_string(1, "3D8").unwrap(), 12346);
+    assert_eq!(service.get_string(2).await.unwrap(), "NkK9");
+    assert_eq!(service.decode_string(2, "NkK9").unwrap(), 12345);
+    // decimal by default
+    assert_eq!(service.get_string(3).await.unwrap(), "12345");
+    assert_eq!(service.decode_string(3, "12345").unwrap(), 12345);
+    assert_eq!(Decimal.encode(12345).unwrap(), "12345");
+
+    let mut guard = service.get_tag_guard(1).await.unwrap();
+    assert_eq!(guard.get_string().await.unwrap(), "3D8");
+}
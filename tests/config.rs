@@ -0,0 +1,116 @@
+use std::time::Duration;
+
+use leaves::config::{LogFormat, ServerConfig};
+use leaves::Error;
+
+const TOML: &str = r#"
+[backend]
+url = "http://127.0.0.1:8080"
+
+[listen]
+http = "0.0.0.0:8081"
+metrics = "0.0.0.0:9090"
+
+[segment]
+max_step = 100000
+segment_duration = "5m"
+
+[[tags]]
+tag = 1
+is_strict = true
+max_step = 1000
+segment_duration = "1h 30m"
+max_value = 9999999999
+
+[log]
+level = "debug"
+format = "json"
+"#;
+
+const YAML: &str = r#"
+backend:
+  url: http://127.0.0.1:8080
+listen:
+  http: 0.0.0.0:8081
+  metrics: 0.0.0.0:9090
+segment:
+  max_step: 100000
+  segment_duration: 5m
+tags:
+  - tag: 1
+    is_strict: true
+    max_step: 1000
+    segment_duration: 1h 30m
+    max_value: 9999999999
+log:
+  level: debug
+  format: json
+"#;
+
+#[test]
+fn test_toml_and_yaml() {
+    let config = ServerConfig::from_toml_str(TOML).unwrap();
+    assert_eq!(config, ServerConfig::from_yaml_str(YAML).unwrap());
+    assert_eq!(config.listen.http, "0.0.0.0:8081".parse().unwrap());
+    assert_eq!(config.segment.max_step, 100_000);
+    assert_eq!(config.segment.segment_duration, Duration::from_secs(5 * 60));
+    assert_eq!(
+        config.segment.update_cache_interval,
+        Duration::from_secs(60)
+    );
+    assert_eq!(config.log.format, LogFormat::Json);
+
+    let tag_config = config.segment_config(1);
+    assert!(tag_config.is_strict);
+    assert!(!config.segment.is_strict);
+    assert_eq!(tag_config.max_step, 1000);
+    assert_eq!(tag_config.segment_duration, Duration::from_secs(90 * 60));
+    assert_eq!(tag_config.max_value, Some(9_999_999_999));
+    assert_eq!(config.segment.max_value, None);
+    assert_eq!(config.segment_config(2), config.segment);
+}
+
+#[test]
+fn test_env_overrides() {
+    let mut config = ServerConfig::from_toml_str(TOML).unwrap();
+    let vars = vec![
+        ("LEAVES_SEGMENT_MAX_STEP", "2000"),
+        ("LEAVES_SEGMENT_DURATION", "10s"),
+        ("LEAVES_SEGMENT_IS_STRICT", "true"),
+        ("LEAVES_SEGMENT_REUSE_FREE_RANGES", "true"),
+        ("LEAVES_LOG_FORMAT", "plain"),
+        ("PATH", "/usr/bin"),
+    ];
+    config
+        .apply_env(vars.into_iter().map(|(k, v)| (k.into(), v.into())))
+        .unwrap();
+    assert_eq!(config.segment.max_step, 2000);
+    assert_eq!(config.segment.segment_duration, Duration::from_secs(10));
+    assert!(config.segment.is_strict);
+    assert!(config.segment.reuse_free_ranges);
+    assert_eq!(config.log.format, LogFormat::Plain);
+
+    let err = config
+        .apply_env(vec![("LEAVES_SEGMENT_MAX_STEP".into(), "many".into())])
+        .unwrap_err();
+    assert!(err.to_string().contains("LEAVES_SEGMENT_MAX_STEP"));
+}
+
+#[test]
+fn test_validate() {
+    let mut config = ServerConfig::default();
+    config.backend.url = "oracle://localhost".into();
+    config.segment.max_step = 0;
+    config.segment.warn_percent = 120;
+    config.log.level = "verbose".into();
+    let err = config.validate().unwrap_err().to_string();
+    assert!(err.contains("unsupported scheme `oracle`"));
+    assert!(err.contains("segment.max_step"));
+    assert!(err.contains("segment.warn_percent"));
+    assert!(err.contains("log.level"));
+
+    assert!(matches!(
+        ServerConfig::from_toml_str("[segment]\nmax_stpe = 1"),
+        Err(Error::InvalidConfig(_))
+    ));
+}
@@ -0,0 +1,229 @@
+//! Many generators with caches of their own share a backend failing at random,
+//! no ID may be handed out twice and every generator hands out increasing IDs.
+//!
+//! Each generator draws its faults from `LEAVES_SEED`, or a random seed which is printed.
+//! Tasks still interleave differently from run to run, so a seed doesn't replay a failure.
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use leaves::dao::mock::{Latency, MockLeafDao, Operation};
+use leaves::segment::Config;
+use leaves::{Error, Leaf, LeafDao, Result, SegmentIDGen};
+
+const INSTANCES: usize = 4;
+const TAGS: i32 = 8;
+const IDS: usize = 500;
+/// chance of a fault in a call
+const FAULT_RATE: f64 = 0.1;
+
+/// Updates fail at random, either before reaching the backend or after it applied them.
+struct Flaky {
+    dao: Arc<dyn LeafDao + Send + Sync>,
+    rng: Mutex<fastrand::Rng>,
+}
+
+enum Fault {
+    None,
+    Before,
+    After,
+}
+
+impl Flaky {
+    fn new(dao: Arc<dyn LeafDao + Send + Sync>, seed: u64) -> Self {
+        Self {
+            dao,
+            rng: Mutex::new(fastrand::Rng::with_seed(seed)),
+        }
+    }
+
+    fn fault(&self) -> Fault {
+        let rng = self.rng.lock().unwrap();
+        if rng.f64() >= FAULT_RATE {
+            Fault::None
+        } else if rng.bool() {
+            Fault::Before
+        } else {
+            Fault::After
+        }
+    }
+
+    async fn update(
+        &self,
+        update: impl std::future::Future<Output = Result<Leaf>>,
+    ) -> Result<Leaf> {
+        match self.fault() {
+            Fault::None => update.await,
+            Fault::Before => Err(Error::FaultInjected),
+            // the range is lost, like when the connection drops before the reply
+            Fault::After => update.await.and(Err(Error::FaultInjected)),
+        }
+    }
+}
+
+#[async_trait]
+impl LeafDao for Flaky {
+    async fn leaves(&self) -> Result<Vec<Leaf>> {
+        self.dao.leaves().await
+    }
+
+    async fn leaf(&self, tag: i32) -> Result<Leaf> {
+        self.dao.leaf(tag).await
+    }
+
+    async fn insert(&self, leaf: Leaf) -> Result<()> {
+        self.dao.insert(leaf).await
+    }
+
+    async fn tags(&self) -> Result<Vec<i32>> {
+        self.dao.tags().await
+    }
+
+    async fn update_max(&self, tag: i32) -> Result<Leaf> {
+        self.update(self.dao.update_max(tag)).await
+    }
+
+    async fn update_max_by_step(&self, tag: i32, step: i32) -> Result<Leaf> {
+        self.update(self.dao.update_max_by_step(tag, step)).await
+    }
+
+    async fn update_step(&self, tag: i32, step: i32) -> Result<Leaf> {
+        self.dao.update_step(tag, step).await
+    }
+
+    async fn delete(&self, tag: i32) -> Result<()> {
+        self.dao.delete(tag).await
+    }
+
+    async fn set_max_id_if_greater(&self, tag: i32, max_id: i64) -> Result<Leaf> {
+        self.dao.set_max_id_if_greater(tag, max_id).await
+    }
+
+    async fn upsert(&self, leaf: Leaf) -> Result<()> {
+        self.dao.upsert(leaf).await
+    }
+}
+
+fn seed() -> u64 {
+    let seed = match std::env::var("LEAVES_SEED") {
+        Ok(seed) => seed.parse().expect("LEAVES_SEED"),
+        Err(_) => fastrand::u64(..),
+    };
+    println!("LEAVES_SEED={}", seed);
+    seed
+}
+
+/// Get `IDS` IDs of `tag`, retrying on faults.
+async fn take(service: Arc<SegmentIDGen<dyn LeafDao + Send + Sync>>, tag: i32) -> Vec<i64> {
+    let mut ids = Vec::with_capacity(IDS);
+    let mut failures = 0;
+    while ids.len() < IDS {
+        match service.get(tag).await {
+            Ok(id) => ids.push(id),
+            Err(Error::FaultInjected) | Err(Error::BothSegmentsNotReady) => {
+                failures += 1;
+                assert!(failures < 10 * IDS, "tag {} keeps failing", tag);
+                tokio::time::delay_for(Duration::from_millis(1)).await;
+            }
+            Err(err) => panic!("tag {}: {:?}", tag, err),
+        }
+    }
+    ids
+}
+
+#[cfg(any(feature = "file", feature = "sqlite"))]
+fn temp_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "leaves-correctness-{}-{}",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+async fn check(dao: Arc<dyn LeafDao + Send + Sync>) {
+    for tag in 0..TAGS {
+        dao.insert(Leaf {
+            tag,
+            max_id: 0,
+            step: 10 + tag,
+        })
+        .await
+        .unwrap();
+    }
+    let seed = seed();
+
+    let mut services = vec![];
+    let mut tasks = vec![];
+    for instance in 0..INSTANCES {
+        let dao: Arc<dyn LeafDao + Send + Sync> =
+            Arc::new(Flaky::new(dao.clone(), seed.wrapping_add(instance as u64)));
+        let mut service = SegmentIDGen::new(dao, Config::new().set_max_step(100));
+        service.init().await.unwrap();
+        let service = Arc::new(service);
+        for tag in 0..TAGS {
+            let task = tokio::spawn(take(service.clone(), tag));
+            tasks.push((instance, tag, task));
+        }
+        services.push(service);
+    }
+
+    let mut issued = (0..TAGS).map(|_| HashSet::new()).collect::<Vec<_>>();
+    for (instance, tag, task) in tasks {
+        let ids = task.await.unwrap();
+        assert!(
+            ids.windows(2).all(|pair| pair[0] < pair[1]),
+            "instance {} went backwards on tag {}",
+            instance,
+            tag
+        );
+        for id in ids {
+            assert!(
+                issued[tag as usize].insert(id),
+                "{} of tag {} issued twice",
+                id,
+                tag
+            );
+        }
+    }
+    // wait for segments loading in background, the runtime drops them mid-query otherwise
+    for service in services {
+        service.shutdown().await.unwrap();
+    }
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn test_mock_dao() {
+    let dao = MockLeafDao::default();
+    dao.clear_latency();
+    for operation in [Operation::UpdateMax, Operation::UpdateMaxByStep] {
+        dao.set_latency(
+            operation,
+            Latency::Uniform(Duration::from_millis(0), Duration::from_millis(2)),
+        );
+    }
+    check(Arc::new(dao)).await;
+}
+
+#[cfg(feature = "file")]
+#[tokio::test(threaded_scheduler)]
+async fn test_file_dao() {
+    let path = temp_dir("file").join("leaves.json");
+    check(Arc::new(leaves::dao::FileLeafDao::new(path))).await;
+}
+
+#[cfg(feature = "sqlite")]
+#[tokio::test(threaded_scheduler)]
+async fn test_sqlite_dao() {
+    let url = format!(
+        "sqlite://{}",
+        temp_dir("sqlite").join("leaves.db").display()
+    );
+    let dao = leaves::dao::SqliteLeafDao::new(&url).await.unwrap();
+    dao.create_table().await.unwrap();
+    check(Arc::new(dao)).await;
+}
@@ -0,0 +1,95 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use fs2::FileExt;
+
+use leaves::dao::FileLeafDao;
+use leaves::{Error, Leaf, LeafDao};
+
+fn temp_path(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("leaves-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir.join("leaves.json")
+}
+
+#[tokio::test]
+async fn test_file_dao() {
+    let path = temp_path("dao");
+    let dao = FileLeafDao::new(&path);
+    assert!(dao.leaves().await.unwrap().is_empty());
+    dao.insert(Leaf {
+        tag: 1,
+        max_id: 0,
+        step: 100,
+    })
+    .await
+    .unwrap();
+    assert_eq!(dao.update_max(1).await.unwrap().max_id, 100);
+    assert_eq!(dao.update_max_by_step(1, 10).await.unwrap().max_id, 110);
+    let overwrite = Leaf {
+        tag: 1,
+        max_id: 0,
+        step: 1,
+    };
+    assert!(matches!(dao.insert(overwrite).await, Err(Error::TagExists)));
+    assert_eq!(dao.leaf(1).await.unwrap().max_id, 110);
+    assert!(matches!(dao.update_max(2).await, Err(Error::TagNotExist)));
+
+    // survives a restart
+    let dao = FileLeafDao::new(&path);
+    assert_eq!(dao.leaf(1).await.unwrap().max_id, 110);
+    assert_eq!(dao.set_max_id_if_greater(1, 50).await.unwrap().max_id, 110);
+    dao.delete(1).await.unwrap();
+    assert!(dao.tags().await.unwrap().is_empty());
+    assert!(!path.with_extension("json.tmp").exists());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn test_file_dao_concurrent() {
+    let path = temp_path("concurrent");
+    FileLeafDao::new(&path)
+        .insert(Leaf {
+            tag: 1,
+            max_id: 0,
+            step: 10,
+        })
+        .await
+        .unwrap();
+    // independent instances lock the file like separate processes do
+    let tasks = (0..40)
+        .map(|_| {
+            let dao = FileLeafDao::new(&path);
+            tokio::spawn(async move { dao.update_max(1).await.unwrap().max_id })
+        })
+        .collect::<Vec<_>>();
+    let mut ends = HashSet::new();
+    for task in tasks {
+        assert!(ends.insert(task.await.unwrap()));
+    }
+    assert_eq!(ends, (1..=40).map(|i| i * 10).collect());
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn test_file_dao_locked() {
+    let path = temp_path("locked");
+    let dao = FileLeafDao::new(&path);
+    dao.insert(Leaf {
+        tag: 1,
+        max_id: 0,
+        step: 10,
+    })
+    .await
+    .unwrap();
+
+    let lock = File::open(path.with_extension("json.lock")).unwrap();
+    lock.lock_exclusive().unwrap();
+    let task = tokio::spawn(async move { dao.update_max(1).await.unwrap().max_id });
+    tokio::time::delay_for(Duration::from_millis(200)).await;
+    let leaves: Vec<Leaf> = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+    assert_eq!(leaves[0].max_id, 0, "updated without the lock");
+    lock.unlock().unwrap();
+    assert_eq!(task.await.unwrap(), 10);
+}
@@ -0,0 +1,85 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use leaves::format::{IdFormatter, Tz};
+use leaves::Error;
+
+/// 2026-10-17 16:30:00 UTC, past midnight in Shanghai
+fn now() -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(1_792_254_600)
+}
+
+#[test]
+fn test_format() {
+    let formatter = IdFormatter::new("{seq}{luhn}").unwrap();
+    assert_eq!(formatter.format(7992739871).unwrap(), "79927398713");
+    let formatter = IdFormatter::new("{seq}{damm}").unwrap();
+    assert_eq!(formatter.format(572).unwrap(), "5724");
+    let formatter = IdFormatter::new("{{{seq:03}}}").unwrap();
+    assert_eq!(formatter.format(5).unwrap(), "{005}");
+
+    let formatter = IdFormatter::new("INV-{yy-MM-dd}-{seq}{damm}").unwrap();
+    assert_eq!(formatter.format_at(42, now()).unwrap(), "INV-26-10-17-425");
+    let formatter = formatter.set_time_zone(Tz::Asia__Shanghai);
+    let id = formatter.format_at(42, now()).unwrap();
+    assert_eq!(id, "INV-26-10-18-422");
+    assert_eq!(formatter.parse(&id).unwrap(), 42);
+
+    let formatter = IdFormatter::new("{yyyyMMddHHmmss}{seq}").unwrap();
+    assert_eq!(formatter.format_at(7, now()).unwrap(), "202610171630007");
+
+    assert!(matches!(formatter.format(-1), Err(Error::InvalidId(_))));
+}
+
+#[test]
+fn test_parse() {
+    let formatter = IdFormatter::new("ORD{yyyyMMdd}{seq:08}{luhn}").unwrap();
+    for &id in [0, 1, 123, 99_999_999, 123_456_789_012].iter() {
+        let formatted = formatter.format_at(id, now()).unwrap();
+        assert_eq!(formatter.parse(&formatted).unwrap(), id, "{}", formatted);
+    }
+    let formatted = formatter.format_at(123, now()).unwrap();
+    assert_eq!(formatted, "ORD20261017000001236");
+    for invalid in [
+        // wrong check digit
+        "ORD20261017000001237",
+        "INV20261017000001236",
+        "ORD20261017000001236 ",
+        // shorter than padded
+        "ORD202610170001232",
+        "ORD2026101x000001236",
+        "",
+    ]
+    .iter()
+    {
+        assert!(
+            matches!(formatter.parse(invalid), Err(Error::InvalidId(_))),
+            "{}",
+            invalid
+        );
+    }
+}
+
+#[test]
+fn test_invalid_template() {
+    for template in [
+        "ORD",
+        "{seq}{seq}",
+        "{seq}{foo}",
+        "{seq",
+        "{seq}}",
+        "{seq:8}",
+        "{seq:00}",
+        "{seq}1",
+        "{seq}{luhn}1",
+    ]
+    .iter()
+    {
+        assert!(
+            matches!(IdFormatter::new(template), Err(Error::InvalidConfig(_))),
+            "{}",
+            template
+        );
+    }
+    // text after digits of fixed widths may start with a digit
+    assert!(IdFormatter::new("{seq}-1").is_ok());
+}
@@ -0,0 +1,64 @@
+use std::sync::Arc;
+
+use leaves::dao::MockLeafDao;
+use leaves::segment::Config;
+use leaves::{Leaf, LeafDao, SegmentIDGen};
+
+#[tokio::test]
+async fn test_render() {
+    let dao = Arc::new(MockLeafDao::default());
+    dao.clear_latency();
+    dao.insert(Leaf {
+        tag: 7,
+        max_id: 0,
+        step: 10,
+    })
+    .await
+    .unwrap();
+    let mut service = SegmentIDGen::new(dao, Config::new());
+    service.init().await.unwrap();
+    for _ in 0..25 {
+        service.get(7).await.unwrap();
+    }
+
+    let body = leaves::metrics::render();
+    assert!(
+        body.contains(r#"leaves_ids_issued_total{tag="7"} 25"#),
+        "{}",
+        body
+    );
+    assert!(body.contains(r#"leaves_segment_switches_total{tag="7"}"#));
+    assert!(body.contains(r#"leaves_step{tag="7"}"#));
+    assert!(body.contains(r#"leaves_dao_duration_seconds_bucket{op="update_max""#));
+    assert!(body.contains(r#"leaves_dao_duration_seconds_count{op="update_max"}"#));
+}
+
+#[cfg(feature = "periodic")]
+#[tokio::test]
+async fn test_usage_of_periodic_tag() {
+    use leaves::periodic::{Periodic, Tz};
+
+    let dao = Arc::new(MockLeafDao::default());
+    dao.clear_latency();
+    dao.insert(Leaf {
+        tag: 3,
+        max_id: 0,
+        step: 10,
+    })
+    .await
+    .unwrap();
+    let mut service = SegmentIDGen::new(dao, Config::new());
+    service.set_tag_partition(3, Periodic::daily(Tz::UTC));
+    service.init().await.unwrap();
+    service.get(3).await.unwrap();
+
+    // 10 of the day's 1_000_000 IDs, not the share of the date encoded in them
+    let body = leaves::metrics::render();
+    let usage: f64 = body
+        .lines()
+        .find_map(|line| line.strip_prefix(r#"leaves_max_value_usage_ratio{tag="3"} "#))
+        .expect("usage of tag 3")
+        .parse()
+        .unwrap();
+    assert!(usage < 0.001, "{}", usage);
+}
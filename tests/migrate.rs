@@ -0,0 +1,86 @@
+use leaves::dao::MockLeafDao;
+use leaves::migrate::{self, Change, DumpFormat};
+use leaves::{Leaf, LeafDao};
+
+fn leaf(tag: i32, max_id: i64, step: i32) -> Leaf {
+    Leaf { tag, max_id, step }
+}
+
+#[tokio::test]
+async fn test_migrate() {
+    let source = MockLeafDao::default();
+    let target = MockLeafDao::default();
+    for leaf in [leaf(1, 1000, 100), leaf(2, 50, 10), leaf(3, 10, 10)] {
+        source.insert(leaf).await.unwrap();
+    }
+    target.insert(leaf(2, 500, 10)).await.unwrap();
+    target.insert(leaf(3, 0, 1)).await.unwrap();
+
+    let changes = migrate::migrate(&source, &target, 100, true).await.unwrap();
+    assert_eq!(
+        changes,
+        vec![
+            Change::Insert(leaf(1, 1100, 100)),
+            Change::Unchanged(leaf(2, 500, 10)),
+            Change::Update {
+                from: leaf(3, 0, 1),
+                to: leaf(3, 110, 10)
+            },
+        ]
+    );
+    assert_eq!(
+        changes[2].to_string(),
+        "~ tag 3: max_id 0 -> 110, step 1 -> 10"
+    );
+    assert!(matches!(
+        target.leaf(1).await,
+        Err(leaves::Error::TagNotExist)
+    ));
+
+    migrate::migrate(&source, &target, 100, false)
+        .await
+        .unwrap();
+    assert_eq!(target.leaf(1).await.unwrap(), leaf(1, 1100, 100));
+    assert_eq!(target.leaf(2).await.unwrap(), leaf(2, 500, 10));
+    assert_eq!(target.leaf(3).await.unwrap(), leaf(3, 110, 10));
+
+    assert!(matches!(
+        migrate::migrate(&source, &target, -1000, true).await,
+        Err(leaves::Error::InvalidConfig(_))
+    ));
+}
+
+#[tokio::test]
+async fn test_dump() {
+    let source = MockLeafDao::default();
+    source.insert(leaf(2, 50, 10)).await.unwrap();
+    source.insert(leaf(1, 1000, 100)).await.unwrap();
+
+    for format in [DumpFormat::Json, DumpFormat::Csv] {
+        let mut dump = vec![];
+        migrate::export(&source, &mut dump, format).await.unwrap();
+        assert_eq!(
+            migrate::read_dump(dump.as_slice(), format).unwrap(),
+            vec![leaf(1, 1000, 100), leaf(2, 50, 10)]
+        );
+
+        let target = MockLeafDao::default();
+        migrate::import(&target, dump.as_slice(), format, 10, false)
+            .await
+            .unwrap();
+        assert_eq!(target.leaf(1).await.unwrap().max_id, 1010);
+    }
+
+    let mut dump = vec![];
+    migrate::export(&source, &mut dump, DumpFormat::Csv)
+        .await
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(dump).unwrap(),
+        "tag,max_id,step\n1,1000,100\n2,50,10\n"
+    );
+    assert!(matches!(
+        migrate::read_dump("tag,max_id,step\n1,x,1\n".as_bytes(), DumpFormat::Csv),
+        Err(leaves::Error::InvalidDump(_))
+    ));
+}
@@ -26,6 +26,16 @@ async fn test_with_mongodb() {
         .await
         .unwrap();
     }
+    let duplicate = Leaf {
+        tag: tags[0],
+        max_id: 5000,
+        step: 1000,
+    };
+    assert!(matches!(
+        dao.insert(duplicate).await,
+        Err(leaves::Error::TagExists)
+    ));
+    assert_eq!(dao.leaf(tags[0]).await.unwrap().max_id, 0);
     let mut service = SegmentIDGen::new(dao, Config::new());
     service.init().await.unwrap();
     let service = Arc::new(service);
@@ -49,3 +59,32 @@ async fn test_with_mongodb() {
     }
     println!("{}ms", start.elapsed().as_millis());
 }
+
+#[tokio::test]
+async fn test_options_from_url() {
+    use leaves::dao::MongoLeafDaoOptions;
+    use mongodb::options::{Acknowledgment, ReadConcern, ReadPreference};
+
+    let defaults = MongoLeafDaoOptions::default();
+    let write_concern = defaults.write_concern.clone().unwrap();
+    assert_eq!(write_concern.w, Some(Acknowledgment::Majority));
+    assert_eq!(write_concern.journal, Some(true));
+    assert_eq!(defaults.read_concern, Some(ReadConcern::majority()));
+    assert_eq!(defaults.read_preference, Some(ReadPreference::Primary));
+
+    let options = ClientOptions::parse("mongodb://localhost/leaves")
+        .await
+        .unwrap();
+    assert_eq!(MongoLeafDaoOptions::from_client_options(&options), defaults);
+
+    let options = ClientOptions::parse("mongodb://localhost/leaves?w=2&readConcernLevel=local")
+        .await
+        .unwrap();
+    let options = MongoLeafDaoOptions::from_client_options(&options);
+    assert_eq!(
+        options.write_concern.unwrap().w,
+        Some(Acknowledgment::Nodes(2))
+    );
+    assert_eq!(options.read_concern, Some(ReadConcern::local()));
+    assert_eq!(options.read_preference, defaults.read_preference);
+}
@@ -0,0 +1,74 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use leaves::dao::MockLeafDao;
+use leaves::obfuscate::Feistel;
+use leaves::segment::Config;
+use leaves::{Error, Leaf, LeafDao, SegmentIDGen};
+
+#[test]
+fn test_bijection() {
+    for bits in 1..=16 {
+        for &secret in [0, 1, 0xdead_beef].iter() {
+            let feistel = Feistel::new(bits, secret);
+            let domain = 1i64 << bits;
+            let mut seen = HashSet::new();
+            for id in 0..domain {
+                let encoded = feistel.encode(id).unwrap();
+                assert!((0..domain).contains(&encoded), "{} bits", bits);
+                assert!(seen.insert(encoded), "collision of {} bits", bits);
+                assert_eq!(feistel.decode(encoded).unwrap(), id, "{} bits", bits);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_scrambled() {
+    let feistel = Feistel::new(32, 42);
+    let encoded = (0..100)
+        .map(|id| feistel.encode(id).unwrap())
+        .collect::<Vec<_>>();
+    assert!(encoded.windows(2).any(|pair| pair[0] > pair[1]));
+    // another secret, another permutation
+    let other = Feistel::new(32, 43);
+    assert!((0..100).any(|id| other.encode(id).unwrap() != encoded[id as usize]));
+
+    let feistel = Feistel::new(63, 42);
+    for &id in [0, 1, i64::MAX - 1, i64::MAX].iter() {
+        assert_eq!(feistel.decode(feistel.encode(id).unwrap()).unwrap(), id);
+    }
+    let feistel = Feistel::new(8, 42);
+    assert!(matches!(feistel.encode(256), Err(Error::InvalidId(_))));
+    assert!(matches!(feistel.encode(-1), Err(Error::InvalidId(_))));
+}
+
+#[tokio::test]
+async fn test_obfuscated_tag() {
+    let dao = Arc::new(MockLeafDao::default());
+    dao.clear_latency();
+    for tag in 1..=2 {
+        dao.insert(Leaf {
+            tag,
+            max_id: 0,
+            step: 10,
+        })
+        .await
+        .unwrap();
+    }
+    let mut service = SegmentIDGen::new(dao, Config::new().set_max_step(10));
+    service.set_tag_obfuscator(1, Feistel::new(5, 7));
+    service.init().await.unwrap();
+
+    let mut ids = HashSet::new();
+    for expected in 0..32 {
+        let id = service.get(1).await.unwrap();
+        assert!(ids.insert(id));
+        assert_eq!(service.deobfuscate(1, id).unwrap(), expected);
+    }
+    // IDs allocated past 5 bits can't be scrambled
+    assert!(matches!(service.get(1).await, Err(Error::TagExhausted)));
+    // other tags are untouched
+    assert_eq!(service.get(2).await.unwrap(), 0);
+    assert_eq!(service.deobfuscate(2, 0).unwrap(), 0);
+}
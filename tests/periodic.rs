@@ -0,0 +1,102 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use leaves::clock::ManualClock;
+use leaves::dao::MockLeafDao;
+use leaves::periodic::{Period, Periodic, Tz};
+use leaves::segment::Config;
+use leaves::{Error, Leaf, LeafDao, SegmentIDGen};
+
+/// 2026-10-17 23:59:00 UTC
+const BEFORE_MIDNIGHT: u64 = 1_792_281_540;
+
+fn at(secs: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+#[test]
+fn test_period() {
+    // 2026-10-17 16:30:00 UTC, past midnight in Shanghai
+    let now = at(1_792_254_600);
+    assert_eq!(Periodic::daily(Tz::UTC).period(now), 20261017);
+    assert_eq!(Periodic::daily(Tz::Asia__Shanghai).period(now), 20261018);
+    assert_eq!(Periodic::monthly(Tz::UTC).period(now), 202610);
+    assert_eq!(Periodic::new(Period::Year, Tz::UTC).period(now), 2026);
+    // 2026-12-31 23:59:00 UTC
+    assert_eq!(
+        Periodic::yearly(Tz::Asia__Tokyo).period(at(1_798_761_540)),
+        2027
+    );
+
+    let periodic = Periodic::daily(Tz::UTC);
+    assert_eq!(periodic.split(20261017000123), (20261017, 123));
+    assert_eq!(periodic.format(20261017000123), "20261017-000123");
+    assert_eq!(periodic.set_digits(4).format(202610170123), "20261017-0123");
+}
+
+async fn service(
+    dao: Arc<MockLeafDao>,
+    periodic: Periodic,
+) -> (SegmentIDGen<MockLeafDao>, Arc<ManualClock>) {
+    dao.clear_latency();
+    dao.insert(Leaf {
+        tag: 1,
+        max_id: 0,
+        step: 10,
+    })
+    .await
+    .unwrap();
+    let clock = Arc::new(ManualClock::at(at(BEFORE_MIDNIGHT)));
+    let mut service = SegmentIDGen::new(dao, Config::new().set_max_step(10));
+    service.set_clock(clock.clone());
+    service.set_tag_partition(1, periodic);
+    service.init().await.unwrap();
+    (service, clock)
+}
+
+#[tokio::test]
+async fn test_daily_reset() {
+    let dao = Arc::new(MockLeafDao::default());
+    let (service, clock) = service(dao.clone(), Periodic::daily(Tz::UTC)).await;
+    for expected in 20261017000000..20261017000003 {
+        assert_eq!(service.get(1).await.unwrap(), expected);
+    }
+    // the next segment of the day is loaded, and never handed out
+    tokio::time::delay_for(Duration::from_millis(20)).await;
+    clock.advance(Duration::from_secs(120));
+    for expected in 20261018000000..20261018000025 {
+        assert_eq!(service.get(1).await.unwrap(), expected);
+    }
+    assert_eq!(dao.leaf(1).await.unwrap().max_id, 20261018000030);
+}
+
+#[tokio::test]
+async fn test_period_exhausted() {
+    let dao = Arc::new(MockLeafDao::default());
+    let periodic = Periodic::daily(Tz::UTC).set_digits(1);
+    let (service, clock) = service(dao.clone(), periodic).await;
+    for expected in 202610170..202610180 {
+        assert_eq!(service.get(1).await.unwrap(), expected);
+    }
+    assert!(matches!(service.get(1).await, Err(Error::TagExhausted)));
+    clock.advance(Duration::from_secs(120));
+    assert_eq!(service.get(1).await.unwrap(), 202610180);
+}
+
+#[tokio::test]
+async fn test_update_within_period() {
+    let dao = Arc::new(MockLeafDao::default());
+    let periodic = Periodic::daily(Tz::UTC).set_digits(2);
+    let (service, _) = service(dao.clone(), periodic).await;
+    assert_eq!(service.get(1).await.unwrap(), 2026101700);
+    // refilling by hand stops at the end of the day as well
+    let mut result = Ok(());
+    for _ in 0..20 {
+        result = service.update(1).await;
+        if result.is_err() {
+            break;
+        }
+    }
+    assert!(matches!(result, Err(Error::TagExhausted)));
+    assert_eq!(dao.leaf(1).await.unwrap().max_id, 2026101800);
+}
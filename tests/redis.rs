@@ -1,27 +1,447 @@
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+use leaves::dao::redis::{key_slot, RedisDao};
+use leaves::segment::Config;
 use leaves::{Leaf, LeafDao, SegmentIDGen};
 
 #[tokio::test]
 async fn test_with_redis() {
     dotenv::dotenv().ok();
-    let mut config = std::env::var("REDIS_URL").expect("REDIS_URL").split(' ');
+    let url = std::env::var("REDIS_URL").expect("REDIS_URL");
+    let mut config = url.split(' ');
     let (address, password) = (config.next().unwrap(), config.next());
-    let dao = Arc::new(
-        leaves::dao::redis::RedisDao::new(address, password)
-            .await
-            .unwrap(),
-    );
-    let mut service = SegmentIDGen::new(dao.clone());
+    let dao = Arc::new(RedisDao::new(address, password).await.unwrap());
+    dao.upsert(Leaf {
+        tag: 1,
+        max_id: 0,
+        step: 1000,
+    })
+    .await
+    .unwrap();
+    let mut service = SegmentIDGen::new(dao.clone(), Config::new());
     service.init().await.unwrap();
-    // dao.insert(Leaf {
-    //     tag: 1,
-    //     max_id: 0,
-    //     step: 1000,
-    // })
-    // .await
-    // .unwrap();
     for _ in 0..10000 {
         service.get(1).await.unwrap();
     }
 }
+
+#[test]
+fn test_key_slot() {
+    assert_eq!(key_slot(b"123456789"), 0x31c3);
+    assert_eq!(key_slot(b"foo"), 12182);
+    assert_eq!(key_slot(b"{user1000}.following"), key_slot(b"user1000"));
+    // empty hash tags are ignored
+    assert_ne!(key_slot(b"foo{}{bar}"), key_slot(b"bar"));
+    assert_eq!(key_slot(b"leaf_alloc:{1}"), key_slot(b"1"));
+}
+
+type Hashes = HashMap<Vec<u8>, HashMap<Vec<u8>, Vec<u8>>>;
+
+/// Nodes of a fake deployment, sharing data and agreeing on which node is the master.
+#[derive(Default)]
+struct Deployment {
+    /// a Redis Cluster redirecting to the master, or replicas refusing writes
+    cluster: bool,
+    hashes: Mutex<Hashes>,
+    master: Mutex<String>,
+    /// nodes dropping every connection
+    down: Mutex<HashSet<String>>,
+    /// data requests served per node
+    served: Mutex<HashMap<String, usize>>,
+    /// replicas acknowledging a `WAIT`
+    replicas: Mutex<i64>,
+    /// `appendonly` and `appendfsync`
+    persistence: Mutex<(&'static str, &'static str)>,
+    /// delay of replies, commands are applied right away
+    stall: Mutex<Option<Duration>>,
+}
+
+fn bulk(data: &[u8]) -> Vec<u8> {
+    let mut reply = format!("${}\r\n", data.len()).into_bytes();
+    reply.extend_from_slice(data);
+    reply.extend_from_slice(b"\r\n");
+    reply
+}
+
+fn array(items: Vec<Vec<u8>>) -> Vec<u8> {
+    let mut reply = format!("*{}\r\n", items.len()).into_bytes();
+    items.into_iter().for_each(|item| reply.extend(item));
+    reply
+}
+
+fn integer(i: i64) -> Vec<u8> {
+    format!(":{}\r\n", i).into_bytes()
+}
+
+fn hmget(hashes: &Hashes, key: &[u8]) -> Vec<u8> {
+    let hash = hashes.get(key).cloned().unwrap_or_default();
+    array(
+        [&b"tag"[..], b"max_id", b"step"]
+            .iter()
+            .map(|field| match hash.get(*field) {
+                Some(value) => bulk(value),
+                None => b"$-1\r\n".to_vec(),
+            })
+            .collect(),
+    )
+}
+
+impl Deployment {
+    async fn node(self: &Arc<Self>) -> String {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let (deployment, node) = (self.clone(), address.clone());
+        tokio::spawn(async move {
+            loop {
+                let (socket, _) = listener.accept().await.unwrap();
+                tokio::spawn(deployment.clone().serve(socket, node.clone()));
+            }
+        });
+        address
+    }
+
+    async fn serve(self: Arc<Self>, socket: TcpStream, node: String) {
+        let mut socket = BufReader::new(socket);
+        loop {
+            // like a crashed node, idle connections are closed once it's down
+            while socket.buffer().is_empty() {
+                if self.down.lock().unwrap().contains(&node) {
+                    return;
+                }
+                let idle = Duration::from_millis(10);
+                match tokio::time::timeout(idle, socket.get_mut().peek(&mut [0])).await {
+                    Ok(Ok(0)) => return,
+                    Ok(_) => break,
+                    Err(_) => {}
+                }
+            }
+            let mut line = String::new();
+            socket.read_line(&mut line).await.unwrap();
+            let mut args = vec![];
+            for _ in 0..line[1..].trim().parse().unwrap() {
+                line.clear();
+                socket.read_line(&mut line).await.unwrap();
+                let mut arg = vec![0; line[1..].trim().parse::<usize>().unwrap() + 2];
+                socket.read_exact(&mut arg).await.unwrap();
+                arg.truncate(arg.len() - 2);
+                args.push(arg);
+            }
+            let reply = self.reply(&node, &args);
+            let stall = *self.stall.lock().unwrap();
+            if let Some(stall) = stall {
+                tokio::time::delay_for(stall).await;
+            }
+            // the client may have given up on the reply
+            if socket.get_mut().write_all(&reply).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    fn reply(&self, node: &str, args: &[Vec<u8>]) -> Vec<u8> {
+        let master = self.master.lock().unwrap().clone();
+        let (host, port) = master.split_once(':').unwrap();
+        let command = String::from_utf8_lossy(&args[0]).to_uppercase();
+        match command.as_str() {
+            "CLIENT" => return b"+OK\r\n".to_vec(),
+            "CLUSTER" => {
+                let master = array(vec![bulk(host.as_bytes()), integer(port.parse().unwrap())]);
+                return array(vec![array(vec![integer(0), integer(16383), master])]);
+            }
+            "SENTINEL" => return array(vec![bulk(host.as_bytes()), bulk(port.as_bytes())]),
+            "ROLE" if node == master => return array(vec![bulk(b"master")]),
+            "ROLE" => return array(vec![bulk(b"slave")]),
+            _ if node != master && self.cluster => {
+                return format!("-MOVED {} {}\r\n", key_slot(&args[1]), master).into_bytes()
+            }
+            _ if node != master => return b"-READONLY replica\r\n".to_vec(),
+            _ => {}
+        }
+        *self
+            .served
+            .lock()
+            .unwrap()
+            .entry(node.to_string())
+            .or_default() += 1;
+        let mut hashes = self.hashes.lock().unwrap();
+        match command.as_str() {
+            "EVAL" if String::from_utf8_lossy(&args[1]).contains("DEL") => {
+                integer(hashes.remove(&args[3]).is_some() as i64)
+            }
+            // the script writing a leaf, unless it exists and isn't to be overwritten
+            "EVAL" if String::from_utf8_lossy(&args[1]).contains("HMSET") => {
+                if hashes.contains_key(&args[3]) && args[7] != b"overwrite" {
+                    return integer(-1);
+                }
+                let hash = hashes.entry(args[3].clone()).or_default();
+                for (field, value) in [&b"tag"[..], b"max_id", b"step"].iter().zip(&args[4..7]) {
+                    hash.insert(field.to_vec(), value.clone());
+                }
+                integer(1)
+            }
+            "HMGET" => hmget(&hashes, &args[1]),
+            "WAIT" => integer(*self.replicas.lock().unwrap()),
+            "CONFIG" => {
+                let (appendonly, appendfsync) = *self.persistence.lock().unwrap();
+                array(vec![
+                    bulk(b"appendonly"),
+                    bulk(appendonly.as_bytes()),
+                    bulk(b"appendfsync"),
+                    bulk(appendfsync.as_bytes()),
+                ])
+            }
+            "KEYS" => array(hashes.keys().map(|key| bulk(key)).collect()),
+            // only the scripts moving `max_id`, up to ARGV[2] if given, are used here
+            "EVAL" => match hashes.get_mut(&args[3]) {
+                Some(hash) => {
+                    let step = match args[4].as_slice() {
+                        b"" => hash[&b"step"[..]].clone(),
+                        step => step.to_vec(),
+                    };
+                    let step: i64 = String::from_utf8(step).unwrap().parse().unwrap();
+                    let max_id = hash.get_mut(&b"max_id"[..]).unwrap();
+                    let value: i64 = String::from_utf8(max_id.clone()).unwrap().parse().unwrap();
+                    if let Some(max_value) = args.get(5) {
+                        let max_value: i64 = String::from_utf8_lossy(max_value).parse().unwrap();
+                        if value > max_value {
+                            return integer(-1);
+                        }
+                    }
+                    *max_id = (value + step).to_string().into_bytes();
+                    hmget(&hashes, &args[3])
+                }
+                None => integer(0),
+            },
+            _ => unreachable!("unexpected command {}", command),
+        }
+    }
+
+    fn served(&self, node: &str) -> usize {
+        self.served.lock().unwrap().get(node).copied().unwrap_or(0)
+    }
+}
+
+#[tokio::test]
+async fn test_cluster_redirection() {
+    let deployment = Arc::new(Deployment {
+        cluster: true,
+        ..Default::default()
+    });
+    let (a, b) = (deployment.node().await, deployment.node().await);
+    *deployment.master.lock().unwrap() = a.clone();
+
+    let dao = RedisDao::cluster(&[&a], None).await.unwrap();
+    dao.insert(Leaf {
+        tag: 1,
+        max_id: 0,
+        step: 10,
+    })
+    .await
+    .unwrap();
+    assert_eq!(dao.update_max(1).await.unwrap().max_id, 10);
+    assert!(deployment
+        .hashes
+        .lock()
+        .unwrap()
+        .contains_key(&b"leaf_alloc:{1}"[..]));
+
+    // the slot moved, `a` redirects to `b`
+    *deployment.master.lock().unwrap() = b.clone();
+    assert_eq!(dao.update_max_by_step(1, 5).await.unwrap().max_id, 15);
+    assert_eq!(dao.update_max(1).await.unwrap().max_id, 25);
+    assert_eq!(deployment.served(&b), 2);
+    assert_eq!(dao.tags().await.unwrap(), vec![1]);
+    assert!(matches!(
+        dao.update_max(2).await,
+        Err(leaves::Error::TagNotExist)
+    ));
+}
+
+#[tokio::test]
+async fn test_sentinel_failover() {
+    let deployment = Arc::new(Deployment::default());
+    let (a, b) = (deployment.node().await, deployment.node().await);
+    let sentinel = deployment.node().await;
+    *deployment.master.lock().unwrap() = a.clone();
+
+    let dao = RedisDao::sentinel(&[&sentinel], "mymaster", None)
+        .await
+        .unwrap()
+        .with_timeout(Duration::from_millis(200));
+    dao.insert(Leaf {
+        tag: 1,
+        max_id: 0,
+        step: 10,
+    })
+    .await
+    .unwrap();
+    assert_eq!(dao.update_max(1).await.unwrap().max_id, 10);
+    assert!(deployment
+        .hashes
+        .lock()
+        .unwrap()
+        .contains_key(&b"leaf_alloc:1"[..]));
+
+    // `a` is gone, `b` is promoted
+    deployment.down.lock().unwrap().insert(a.clone());
+    *deployment.master.lock().unwrap() = b.clone();
+    assert_eq!(dao.update_max(1).await.unwrap().max_id, 20);
+    assert_eq!(deployment.served(&b), 1);
+}
+
+#[tokio::test]
+async fn test_wait_for_replicas() {
+    let deployment = Arc::new(Deployment::default());
+    let a = deployment.node().await;
+    *deployment.master.lock().unwrap() = a.clone();
+    *deployment.replicas.lock().unwrap() = 1;
+
+    let dao = RedisDao::new(&a, None)
+        .await
+        .unwrap()
+        .wait_for_replicas(1, Duration::from_millis(50));
+    dao.insert(Leaf {
+        tag: 1,
+        max_id: 0,
+        step: 10,
+    })
+    .await
+    .unwrap();
+    assert_eq!(dao.update_max(1).await.unwrap().max_id, 10);
+
+    // the change stays on the master, the range is skipped
+    *deployment.replicas.lock().unwrap() = 0;
+    assert!(matches!(
+        dao.update_max(1).await,
+        Err(leaves::Error::NotReplicated(0, 1))
+    ));
+    *deployment.replicas.lock().unwrap() = 2;
+    assert_eq!(dao.update_max(1).await.unwrap().max_id, 30);
+    assert!(matches!(
+        dao.update_max(2).await,
+        Err(leaves::Error::TagNotExist)
+    ));
+
+    // so are writes and deletes
+    *deployment.replicas.lock().unwrap() = 0;
+    let leaf = Leaf {
+        tag: 2,
+        max_id: 0,
+        step: 10,
+    };
+    assert!(matches!(
+        dao.upsert(leaf).await,
+        Err(leaves::Error::NotReplicated(0, 1))
+    ));
+    assert!(matches!(
+        dao.delete(2).await,
+        Err(leaves::Error::NotReplicated(0, 1))
+    ));
+    assert!(matches!(
+        dao.delete(2).await,
+        Err(leaves::Error::TagNotExist)
+    ));
+}
+
+#[tokio::test]
+async fn test_late_reply() {
+    let deployment = Arc::new(Deployment::default());
+    let a = deployment.node().await;
+    *deployment.master.lock().unwrap() = a.clone();
+    let dao = RedisDao::new(&a, None)
+        .await
+        .unwrap()
+        .with_timeout(Duration::from_millis(100));
+    dao.insert(Leaf {
+        tag: 1,
+        max_id: 0,
+        step: 10,
+    })
+    .await
+    .unwrap();
+
+    // applied, but answered after the request is given up
+    *deployment.stall.lock().unwrap() = Some(Duration::from_millis(300));
+    assert!(dao.update_max(1).await.is_err());
+    *deployment.stall.lock().unwrap() = None;
+    tokio::time::delay_for(Duration::from_millis(300)).await;
+    // the late reply of `[0, 10)` is never taken for the reply of another command
+    assert_eq!(dao.update_max(1).await.unwrap().max_id, 20);
+    assert_eq!(dao.leaf(1).await.unwrap().max_id, 20);
+}
+
+#[tokio::test]
+async fn test_check_persistence() {
+    let deployment = Arc::new(Deployment::default());
+    let a = deployment.node().await;
+    *deployment.master.lock().unwrap() = a.clone();
+    let dao = RedisDao::new(&a, None).await.unwrap();
+
+    *deployment.persistence.lock().unwrap() = ("no", "everysec");
+    let warnings = dao.check_persistence().await.unwrap();
+    assert_eq!(warnings.len(), 1);
+    assert!(warnings[0].starts_with("AOF is disabled"));
+
+    *deployment.persistence.lock().unwrap() = ("yes", "everysec");
+    let warnings = dao.check_persistence().await.unwrap();
+    assert!(warnings[0].contains("`everysec`"));
+
+    *deployment.persistence.lock().unwrap() = ("yes", "always");
+    assert!(dao.check_persistence().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_update_max_up_to() {
+    let deployment = Arc::new(Deployment::default());
+    let node = deployment.node().await;
+    *deployment.master.lock().unwrap() = node.clone();
+    let dao = RedisDao::new(node, None).await.unwrap();
+    dao.insert(Leaf {
+        tag: 1,
+        max_id: 0,
+        step: 10,
+    })
+    .await
+    .unwrap();
+    for max_id in [10, 20] {
+        let leaf = dao.update_max_by_step_up_to(1, 10, 15).await.unwrap();
+        assert_eq!(leaf.max_id, max_id);
+    }
+    assert!(matches!(
+        dao.update_max_by_step_up_to(1, 10, 15).await,
+        Err(leaves::Error::TagExhausted)
+    ));
+    assert_eq!(dao.leaf(1).await.unwrap().max_id, 20);
+    assert!(matches!(
+        dao.update_max_by_step_up_to(2, 10, 15).await,
+        Err(leaves::Error::TagNotExist)
+    ));
+}
+
+#[tokio::test]
+async fn test_insert_existing_tag() {
+    let deployment = Arc::new(Deployment::default());
+    let node = deployment.node().await;
+    *deployment.master.lock().unwrap() = node.clone();
+    let dao = RedisDao::new(node, None).await.unwrap();
+    let leaf = Leaf {
+        tag: 1,
+        max_id: 0,
+        step: 10,
+    };
+    dao.insert(leaf).await.unwrap();
+    assert_eq!(dao.update_max(1).await.unwrap().max_id, 10);
+    assert!(matches!(
+        dao.insert(leaf).await,
+        Err(leaves::Error::TagExists)
+    ));
+    assert_eq!(dao.leaf(1).await.unwrap().max_id, 10);
+    // overwriting is left to `upsert`
+    dao.upsert(leaf).await.unwrap();
+    assert_eq!(dao.leaf(1).await.unwrap().max_id, 0);
+}
@@ -0,0 +1,487 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use leaves::clock::ManualClock;
+use leaves::dao::mock::{Call, Operation};
+use leaves::dao::MockLeafDao;
+use leaves::segment::Config;
+use leaves::{Error, FreeRange, Leaf, LeafDao, SegmentIDGen};
+
+#[tokio::test]
+async fn test_mock_dao_management() {
+    let dao = MockLeafDao::default();
+    dao.insert(Leaf {
+        tag: 1,
+        max_id: 100,
+        step: 10,
+    })
+    .await
+    .unwrap();
+    assert_eq!(dao.update_step(1, 20).await.unwrap().step, 20);
+    assert_eq!(dao.set_max_id_if_greater(1, 50).await.unwrap().max_id, 100);
+    assert_eq!(dao.set_max_id_if_greater(1, 500).await.unwrap().max_id, 500);
+    let duplicate = Leaf {
+        tag: 1,
+        max_id: 0,
+        step: 10,
+    };
+    assert!(matches!(dao.insert(duplicate).await, Err(Error::TagExists)));
+    assert_eq!(dao.leaf(1).await.unwrap().max_id, 500);
+    dao.upsert(Leaf {
+        tag: 1,
+        max_id: 0,
+        step: 1,
+    })
+    .await
+    .unwrap();
+    assert_eq!(dao.leaf(1).await.unwrap().step, 1);
+    dao.delete(1).await.unwrap();
+    assert!(matches!(dao.delete(1).await, Err(Error::TagNotExist)));
+    assert!(matches!(
+        dao.update_step(1, 1).await,
+        Err(Error::TagNotExist)
+    ));
+}
+
+#[tokio::test]
+async fn test_step_changed() {
+    let dao = Arc::new(MockLeafDao::default());
+    dao.insert(Leaf {
+        tag: 1,
+        max_id: 0,
+        step: 10,
+    })
+    .await
+    .unwrap();
+    let mut service = SegmentIDGen::new(dao.clone(), Config::new().set_max_step(10));
+    service.init().await.unwrap();
+    for expected in 0..12 {
+        assert_eq!(service.get(1).await.unwrap(), expected);
+    }
+    // segments [0, 10) and [10, 20) are allocated by now
+    assert_eq!(dao.leaf(1).await.unwrap().max_id, 20);
+    dao.update_step(1, 100).await.unwrap();
+    for expected in 12..30 {
+        assert_eq!(service.get(1).await.unwrap(), expected);
+    }
+    // the refill right after the change allocates [20, 120) by the new step
+    assert_eq!(dao.leaf(1).await.unwrap().max_id, 120);
+}
+
+#[tokio::test]
+async fn test_connect_unsupported_scheme() {
+    assert!(matches!(
+        leaves::dao::connect("oracle://localhost").await,
+        Err(Error::InvalidUrl(_))
+    ));
+    for url in ["file", "redis", "sled:", ""].iter() {
+        assert!(
+            matches!(leaves::dao::connect(url).await, Err(Error::InvalidUrl(_))),
+            "{}",
+            url
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_dyn_dao() {
+    let dao: Arc<dyn LeafDao + Send + Sync> = Arc::new(MockLeafDao::default());
+    dao.insert(Leaf {
+        tag: 1,
+        max_id: 0,
+        step: 10,
+    })
+    .await
+    .unwrap();
+
+    let mut gen = SegmentIDGen::new(dao.clone(), Config::new());
+    gen.init().await.unwrap();
+    assert_eq!(gen.get(1).await.unwrap(), 0);
+
+    // a generator over `Arc<dyn LeafDao>` shares the same storage
+    let mut gen: SegmentIDGen<Arc<dyn LeafDao + Send + Sync>> =
+        SegmentIDGen::new(Arc::new(dao), Config::new());
+    gen.init().await.unwrap();
+    assert_eq!(gen.get(1).await.unwrap(), 10);
+}
+
+#[tokio::test]
+async fn test_mock_faults() {
+    let dao = MockLeafDao::default();
+    dao.clear_latency();
+    dao.insert(Leaf {
+        tag: 1,
+        max_id: 0,
+        step: 10,
+    })
+    .await
+    .unwrap();
+
+    dao.script(
+        Operation::UpdateMax,
+        vec![Err(Error::FaultInjected), Ok(()), Err(Error::TagNotExist)],
+    );
+    assert!(matches!(dao.update_max(1).await, Err(Error::FaultInjected)));
+    assert_eq!(dao.update_max(1).await.unwrap().max_id, 10);
+    assert!(matches!(dao.update_max(1).await, Err(Error::TagNotExist)));
+    assert_eq!(dao.update_max(1).await.unwrap().max_id, 20);
+    assert_eq!(dao.calls(Operation::UpdateMax), 4);
+
+    dao.set_error_rate(Operation::Leaf, 1.0);
+    assert!(matches!(dao.leaf(1).await, Err(Error::FaultInjected)));
+    // scripted outcomes come first
+    dao.script(Operation::Leaf, vec![Ok(())]);
+    assert_eq!(dao.leaf(1).await.unwrap().max_id, 20);
+    dao.set_error_rate(Operation::Leaf, 0.5);
+    let mut failed = 0;
+    for _ in 0..1000 {
+        if dao.leaf(1).await.is_err() {
+            failed += 1;
+        }
+    }
+    assert!((400..600).contains(&failed), "{} failed", failed);
+
+    let history = dao.history();
+    assert_eq!(history.len(), 1007);
+    assert_eq!(history[0].operation, Operation::Insert);
+    assert_eq!(
+        history[2],
+        Call {
+            operation: Operation::UpdateMax,
+            tag: Some(1),
+            leaf: Some(Leaf {
+                tag: 1,
+                max_id: 10,
+                step: 10
+            }),
+            failed: false,
+        }
+    );
+    assert!(history[1].failed && history[3].failed);
+    dao.clear_history();
+    assert!(dao.history().is_empty());
+    assert_eq!(dao.calls(Operation::UpdateMax), 0);
+}
+
+#[tokio::test]
+async fn test_refill_while_dao_paused() {
+    let dao = Arc::new(MockLeafDao::default());
+    dao.clear_latency();
+    dao.insert(Leaf {
+        tag: 1,
+        max_id: 0,
+        step: 10,
+    })
+    .await
+    .unwrap();
+    let mut service = SegmentIDGen::new(dao.clone(), Config::new().set_max_step(10));
+    service.init().await.unwrap();
+    assert_eq!(service.get(1).await.unwrap(), 0);
+    assert_eq!(dao.calls(Operation::UpdateMax), 1);
+
+    // the current segment is still served while the next one can't be allocated
+    dao.pause();
+    for expected in 1..10 {
+        assert_eq!(service.get(1).await.unwrap(), expected);
+    }
+    tokio::time::delay_for(Duration::from_millis(10)).await;
+    assert_eq!(dao.calls(Operation::UpdateMaxByStep), 0);
+
+    let service = Arc::new(service);
+    let get = tokio::spawn({
+        let service = service.clone();
+        async move { service.get(1).await }
+    });
+    tokio::time::delay_for(Duration::from_millis(50)).await;
+    assert!(dao
+        .history()
+        .iter()
+        .all(|call| call.operation != Operation::UpdateMaxByStep));
+    dao.resume();
+    assert_eq!(get.await.unwrap().unwrap(), 10);
+    assert_eq!(dao.calls(Operation::UpdateMaxByStep), 1);
+}
+
+#[tokio::test]
+async fn test_refill_failure() {
+    let dao = Arc::new(MockLeafDao::default());
+    dao.clear_latency();
+    dao.insert(Leaf {
+        tag: 1,
+        max_id: 0,
+        step: 10,
+    })
+    .await
+    .unwrap();
+    let mut service = SegmentIDGen::new(dao.clone(), Config::new().set_max_step(10));
+    service.init().await.unwrap();
+
+    // failing refills don't affect the current segment
+    dao.set_error_rate(Operation::UpdateMaxByStep, 1.0);
+    for expected in 0..10 {
+        assert_eq!(service.get(1).await.unwrap(), expected);
+    }
+    assert!(matches!(
+        service.get(1).await,
+        Err(Error::BothSegmentsNotReady)
+    ));
+    let refills = dao
+        .history()
+        .into_iter()
+        .filter(|call| call.operation == Operation::UpdateMaxByStep)
+        .collect::<Vec<_>>();
+    assert!(!refills.is_empty());
+    assert!(refills.iter().all(|call| call.failed));
+    assert_eq!(dao.leaf(1).await.unwrap().max_id, 10);
+
+    // the next call allocates again once the database is back
+    dao.set_error_rate(Operation::UpdateMaxByStep, 0.0);
+    assert_eq!(service.get(1).await.unwrap(), 10);
+    assert_eq!(dao.leaf(1).await.unwrap().max_id, 20);
+}
+
+#[tokio::test]
+async fn test_tag_guard_init_failure() {
+    let dao = Arc::new(MockLeafDao::default());
+    dao.clear_latency();
+    dao.insert(Leaf {
+        tag: 1,
+        max_id: 0,
+        step: 10,
+    })
+    .await
+    .unwrap();
+    let service = SegmentIDGen::new(dao.clone(), Config::lazy());
+    let mut guard = service.get_tag_guard(1).await.unwrap();
+
+    // the guard keeps the buffer when loading the first segment fails
+    dao.set_error_rate(Operation::UpdateMax, 1.0);
+    assert!(guard.get().await.is_err());
+    assert!(guard.get().await.is_err());
+    dao.set_error_rate(Operation::UpdateMax, 0.0);
+    assert_eq!(guard.get().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn test_step_adapts_to_clock() {
+    let dao = Arc::new(MockLeafDao::default());
+    dao.clear_latency();
+    dao.insert(Leaf {
+        tag: 1,
+        max_id: 0,
+        step: 10,
+    })
+    .await
+    .unwrap();
+    let clock = Arc::new(ManualClock::new());
+    let config = Config::new()
+        .set_max_step(80)
+        .set_segment_duration(Duration::from_secs(60));
+    let mut service = SegmentIDGen::new(dao.clone(), config);
+    service.set_clock(clock.clone());
+    service.init().await.unwrap();
+    assert_eq!(service.get(1).await.unwrap(), 0);
+
+    let mut max_id = dao.leaf(1).await.unwrap().max_id;
+    let refills = [
+        // segments lasting less than `segment_duration` double the step, up to `max_step`
+        (0, 20),
+        (59, 40),
+        (0, 80),
+        (0, 80),
+        // in between it's kept
+        (90, 80),
+        // lasting at least twice as long halve it, down to the step in database
+        (120, 40),
+        (120, 20),
+        (300, 10),
+        (300, 10),
+    ];
+    for &(elapsed, step) in refills.iter() {
+        clock.advance(Duration::from_secs(elapsed));
+        service.update(1).await.unwrap();
+        let max = dao.leaf(1).await.unwrap().max_id;
+        assert_eq!(max - max_id, step, "{}s after the last refill", elapsed);
+        max_id = max;
+    }
+}
+
+#[tokio::test]
+async fn test_step_near_i32_max() {
+    let dao = Arc::new(MockLeafDao::default());
+    dao.clear_latency();
+    dao.insert(Leaf {
+        tag: 1,
+        max_id: 0,
+        step: 1_500_000_000,
+    })
+    .await
+    .unwrap();
+    let mut service = SegmentIDGen::new(dao.clone(), Config::new().set_max_step(i32::MAX));
+    service.init().await.unwrap();
+    assert_eq!(service.get(1).await.unwrap(), 0);
+    // doubling would pass `max_step`, so it's kept
+    service.update(1).await.unwrap();
+    assert_eq!(dao.leaf(1).await.unwrap().max_id, 3_000_000_000);
+}
+
+/// IDs handed out after the current segment is replaced while the next one is loaded.
+async fn ids_after_update(config: Config) -> Vec<Result<i64, Error>> {
+    let dao = Arc::new(MockLeafDao::default());
+    dao.clear_latency();
+    dao.insert(Leaf {
+        tag: 1,
+        max_id: 0,
+        step: 10,
+    })
+    .await
+    .unwrap();
+    let mut service = SegmentIDGen::new(dao.clone(), config.set_max_step(10));
+    service.init().await.unwrap();
+    for expected in 0..3 {
+        assert_eq!(service.get(1).await.unwrap(), expected);
+    }
+    // [10, 20) is loaded as the next segment, then [20, 30) replaces [0, 10)
+    tokio::time::delay_for(Duration::from_millis(20)).await;
+    service.update(1).await.unwrap();
+    let mut ids = vec![];
+    for _ in 0..12 {
+        ids.push(service.get(1).await);
+    }
+    ids
+}
+
+#[tokio::test]
+async fn test_strict_mode() {
+    let ids = ids_after_update(Config::new()).await;
+    let ids = ids.into_iter().map(Result::unwrap).collect::<Vec<_>>();
+    assert_eq!(ids[..10], (20..30).collect::<Vec<_>>()[..]);
+    // back to the next segment
+    assert_eq!(ids[10..], [10, 11]);
+
+    let ids = ids_after_update(Config::new().set_strict(true)).await;
+    let ids = ids.into_iter().map(Result::unwrap).collect::<Vec<_>>();
+    assert_eq!(ids[..10], (20..30).collect::<Vec<_>>()[..]);
+    // the next segment is behind, it's skipped for a new one
+    assert_eq!(ids[10..], [30, 31]);
+}
+
+/// Take 0, 1 and 2 of a tag of step 10, then shut down with [10, 20) loaded as the next segment.
+async fn shut_down_after_preload(dao: Arc<MockLeafDao>, config: Config) -> Vec<FreeRange> {
+    let mut service = SegmentIDGen::new(dao, config.set_max_step(10));
+    service.init().await.unwrap();
+    for expected in 0..3 {
+        assert_eq!(service.get(1).await.unwrap(), expected);
+    }
+    tokio::time::delay_for(Duration::from_millis(20)).await;
+    let ranges = service.shutdown().await.unwrap();
+    assert!(matches!(service.get(1).await, Err(Error::ServiceNotReady)));
+    assert!(matches!(
+        service.update(1).await,
+        Err(Error::ServiceNotReady)
+    ));
+    assert!(matches!(
+        service.get_tag_guard(1).await,
+        Err(Error::ServiceNotReady)
+    ));
+    ranges
+}
+
+#[tokio::test]
+async fn test_shutdown_reuses_free_ranges() {
+    let dao = Arc::new(MockLeafDao::default());
+    dao.clear_latency();
+    dao.insert(Leaf {
+        tag: 1,
+        max_id: 0,
+        step: 10,
+    })
+    .await
+    .unwrap();
+    let expected = vec![
+        FreeRange {
+            tag: 1,
+            start: 3,
+            end: 10,
+        },
+        FreeRange {
+            tag: 1,
+            start: 10,
+            end: 20,
+        },
+    ];
+
+    let ranges = shut_down_after_preload(dao.clone(), Config::new()).await;
+    assert_eq!(ranges, expected);
+    // only reported
+    assert_eq!(dao.calls(Operation::PushFreeRange), 0);
+
+    dao.upsert(Leaf {
+        tag: 1,
+        max_id: 0,
+        step: 10,
+    })
+    .await
+    .unwrap();
+    let config = Config::new().set_reuse_free_ranges(true);
+    let ranges = shut_down_after_preload(dao.clone(), config).await;
+    assert_eq!(ranges, expected);
+    assert_eq!(dao.calls(Operation::PushFreeRange), 2);
+
+    // the next instance hands out the ranges left before new ones
+    let mut service = SegmentIDGen::new(dao.clone(), config.set_max_step(10));
+    service.init().await.unwrap();
+    for expected in 3..25 {
+        assert_eq!(service.get(1).await.unwrap(), expected);
+    }
+    assert_eq!(dao.pop_free_range(1).await.unwrap(), None);
+    assert_eq!(dao.leaf(1).await.unwrap().max_id, 30);
+}
+
+#[tokio::test]
+async fn test_max_value() {
+    let dao = Arc::new(MockLeafDao::default());
+    dao.clear_latency();
+    dao.insert(Leaf {
+        tag: 1,
+        max_id: i64::MAX - 5,
+        step: 10,
+    })
+    .await
+    .unwrap();
+    assert!(matches!(dao.update_max(1).await, Err(Error::TagExhausted)));
+    dao.upsert(Leaf {
+        tag: 1,
+        max_id: 20,
+        step: 10,
+    })
+    .await
+    .unwrap();
+    let leaf = dao.update_max_by_step_up_to(1, 10, 20).await.unwrap();
+    assert_eq!(leaf.max_id, 30);
+    assert!(matches!(
+        dao.update_max_by_step_up_to(1, 10, 29).await,
+        Err(Error::TagExhausted)
+    ));
+
+    dao.upsert(Leaf {
+        tag: 1,
+        max_id: 0,
+        step: 10,
+    })
+    .await
+    .unwrap();
+    let config = Config::new().set_max_step(10).set_max_value(24);
+    let mut service = SegmentIDGen::new(dao.clone(), config);
+    service.init().await.unwrap();
+    for expected in 0..=24 {
+        assert_eq!(service.get(1).await.unwrap(), expected);
+    }
+    assert!(matches!(service.get(1).await, Err(Error::TagExhausted)));
+    // not retried on every call
+    let calls = dao.calls(Operation::UpdateMaxByStep);
+    assert!(matches!(service.get(1).await, Err(Error::TagExhausted)));
+    assert_eq!(dao.calls(Operation::UpdateMaxByStep), calls);
+    // [20, 30) was the last range allocated
+    assert_eq!(dao.leaf(1).await.unwrap().max_id, 30);
+}
@@ -0,0 +1,131 @@
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use leaves::dao::SledLeafDao;
+use leaves::{Error, Leaf, LeafDao};
+
+const CHILD_ENV: &str = "LEAVES_SLED_CRASH_CHILD";
+
+fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("leaves-sled-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    path
+}
+
+#[tokio::test]
+async fn test_sled_dao() {
+    let path = temp_path("dao");
+    let dao = SledLeafDao::open(&path).unwrap();
+    dao.insert(Leaf {
+        tag: 1,
+        max_id: 0,
+        step: 100,
+    })
+    .await
+    .unwrap();
+    assert_eq!(dao.update_max(1).await.unwrap().max_id, 100);
+    assert_eq!(dao.update_max_by_step(1, 10).await.unwrap().max_id, 110);
+    assert_eq!(dao.update_step(1, 20).await.unwrap().step, 20);
+    assert_eq!(dao.set_max_id_if_greater(1, 50).await.unwrap().max_id, 110);
+    let overwrite = Leaf {
+        tag: 1,
+        max_id: 0,
+        step: 1,
+    };
+    assert!(matches!(dao.insert(overwrite).await, Err(Error::TagExists)));
+    assert_eq!(dao.leaf(1).await.unwrap().max_id, 110);
+    dao.upsert(overwrite).await.unwrap();
+    assert_eq!(dao.leaf(1).await.unwrap(), overwrite);
+    assert_eq!(dao.tags().await.unwrap(), vec![1]);
+    assert!(matches!(dao.update_max(2).await, Err(Error::TagNotExist)));
+    dao.delete(1).await.unwrap();
+    assert!(matches!(dao.delete(1).await, Err(Error::TagNotExist)));
+}
+
+#[tokio::test(threaded_scheduler)]
+async fn test_sled_dao_concurrent() {
+    let dao = SledLeafDao::open(temp_path("concurrent")).unwrap();
+    dao.insert(Leaf {
+        tag: 1,
+        max_id: 0,
+        step: 10,
+    })
+    .await
+    .unwrap();
+    let tasks = (0..100)
+        .map(|_| {
+            let dao = dao.clone();
+            tokio::spawn(async move { dao.update_max(1).await.unwrap().max_id })
+        })
+        .collect::<Vec<_>>();
+    let mut ends = HashSet::new();
+    for task in tasks {
+        assert!(ends.insert(task.await.unwrap()));
+    }
+    assert_eq!(ends, (1..=100).map(|i| i * 10).collect());
+}
+
+/// Run by `test_sled_crash_recovery` in a child process:
+/// allocates ranges forever, printing the end of each range handed out.
+/// Ignored, as it checks nothing on its own.
+#[tokio::test]
+#[ignore]
+async fn sled_crash_child() {
+    let path = match std::env::var(CHILD_ENV) {
+        Ok(path) => path,
+        Err(_) => return,
+    };
+    let dao = SledLeafDao::open(path).unwrap();
+    let stdout = std::io::stdout();
+    loop {
+        let max_id = dao.update_max(1).await.unwrap().max_id;
+        writeln!(stdout.lock(), "range {}", max_id).unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_sled_crash_recovery() {
+    let path = temp_path("crash");
+    SledLeafDao::open(&path)
+        .unwrap()
+        .insert(Leaf {
+            tag: 1,
+            max_id: 0,
+            step: 10,
+        })
+        .await
+        .unwrap();
+
+    let mut handed_out = HashSet::new();
+    for round in 0..5 {
+        let mut child = Command::new(std::env::current_exe().unwrap())
+            .args(["sled_crash_child", "--exact", "--ignored", "--nocapture"])
+            .env(CHILD_ENV, &path)
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        let (mut last, mut ranges) = (0, 0);
+        for line in stdout
+            .lines()
+            .filter_map(|line| line.unwrap().strip_prefix("range ")?.parse().ok())
+            .take(50 + round * 17)
+        {
+            assert!(handed_out.insert(line), "range {} handed out twice", line);
+            last = line;
+            ranges += 1;
+        }
+        assert_eq!(ranges, 50 + round * 17, "the child stopped allocating");
+        // killed in the middle of an allocation
+        child.kill().unwrap();
+        child.wait().unwrap();
+
+        let dao = SledLeafDao::open(&path).unwrap();
+        let leaf = dao.leaf(1).await.unwrap();
+        assert!(leaf.max_id >= last);
+        let next = dao.update_max(1).await.unwrap().max_id;
+        assert!(handed_out.insert(next), "range {} handed out twice", next);
+    }
+}